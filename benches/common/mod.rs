@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use axum::Router;
+use tower::ServiceExt;
+
+use staticauth::service::{hash_password, ServiceConfig};
+
+pub const HOST: &str = "auth.example.com";
+pub const PASSWORD: &str = "p@ssw0rd";
+
+// Builds a service with alice plus `extra_users` others sharing her password hash.
+pub fn config(extra_users: usize) -> ServiceConfig {
+    let hash = hash_password(PASSWORD).unwrap();
+    let mut users: HashMap<_, _> =
+        (0..extra_users).map(|i| (format!("user{}", i), hash.clone())).collect();
+    users.insert("alice".into(), hash);
+    ServiceConfig::for_tests(users)
+}
+
+pub fn authenticate(password: &str) -> Request<Body> {
+    let body = format!(r#"{{"username":"alice","password":"{}"}}"#, password);
+    Request::post("/authenticate")
+        .header(header::HOST, HOST)
+        .header(header::ORIGIN, format!("https://{}", HOST))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+pub fn userinfo(cookie: &str) -> Request<Body> {
+    Request::get("/userinfo")
+        .header(header::HOST, HOST)
+        .header(header::COOKIE, cookie)
+        .body(Body::empty())
+        .unwrap()
+}
+
+pub async fn sign_in(router: &Router) -> String {
+    let resp = router.clone().oneshot(authenticate(PASSWORD)).await.unwrap();
+    assert_eq!(StatusCode::OK, resp.status());
+    let cookie = resp.headers().get(header::SET_COOKIE).unwrap().to_str().unwrap();
+    cookie.split(';').next().unwrap().to_owned()
+}
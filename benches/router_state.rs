@@ -0,0 +1,92 @@
+mod common;
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::extract::State;
+use axum::http::{Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use axum::Router;
+use axum_extra::extract::cookie::Key;
+use tower::ServiceExt;
+
+use common::{sign_in, userinfo};
+
+const REQUESTS: usize = 20_000;
+
+struct Baseline {
+    users: HashMap<String, String>,
+    key: Vec<u8>,
+}
+
+// Repeats the per-request work of cloning the configuration and deriving the cookie key, which
+// the router did before its state became a shared snapshot.
+async fn clone_config<B>(
+    State(baseline): State<Arc<Baseline>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    black_box(baseline.users.clone());
+    black_box(Key::from(&baseline.key));
+    next.run(req).await
+}
+
+// Issues `REQUESTS` userinfo requests from `tasks` concurrent tasks.
+async fn run(router: &Router, cookie: &str, tasks: usize) -> Duration {
+    let start = Instant::now();
+    let handles: Vec<_> = (0..tasks)
+        .map(|_| {
+            let (router, cookie) = (router.clone(), cookie.to_owned());
+            tokio::spawn(async move {
+                for _ in 0..REQUESTS / tasks {
+                    let resp = router.clone().oneshot(userinfo(&cookie)).await.unwrap();
+                    assert_eq!(StatusCode::OK, resp.status());
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+    runtime.block_on(async {
+        let tasks = std::thread::available_parallelism().map_or(1, |n| n.get()) * 4;
+        for users in [1, 1_000, 10_000] {
+            let config = common::config(users - 1);
+            let baseline = Arc::new(Baseline {
+                users: config.realms.select(None, "/").users.clone(),
+                key: config.session_secret_key.clone(),
+            });
+            let router = config.build().unwrap();
+            let cookie = sign_in(&router).await;
+            let cases = [
+                (
+                    "clone",
+                    router.clone().layer(middleware::from_fn_with_state(baseline, clone_config)),
+                ),
+                ("snapshot", router),
+            ];
+            for (name, router) in cases {
+                run(&router, &cookie, tasks).await;
+                for concurrency in [1, tasks] {
+                    let elapsed = run(&router, &cookie, concurrency).await;
+                    println!(
+                        "userinfo {:<8} {:>6} users {:>3} tasks  {:>10.3?}/req  {:>8.0} req/s",
+                        name,
+                        users,
+                        concurrency,
+                        elapsed / REQUESTS as u32,
+                        REQUESTS as f64 / elapsed.as_secs_f64()
+                    );
+                }
+            }
+        }
+    });
+}
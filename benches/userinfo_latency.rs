@@ -0,0 +1,66 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use axum::http::StatusCode;
+use axum::Router;
+use tower::ServiceExt;
+
+use common::{authenticate, sign_in, userinfo};
+
+const SAMPLES: usize = 500;
+
+async fn measure(router: &Router, cookie: &str) -> Vec<Duration> {
+    let mut samples = Vec::with_capacity(SAMPLES);
+    for _ in 0..SAMPLES {
+        let start = Instant::now();
+        let resp = router.clone().oneshot(userinfo(cookie)).await.unwrap();
+        samples.push(start.elapsed());
+        assert_eq!(StatusCode::OK, resp.status());
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+    samples.sort();
+    samples
+}
+
+fn report(label: &str, samples: &[Duration]) {
+    let percentile = |p: f64| samples[((samples.len() - 1) as f64 * p) as usize];
+    println!(
+        "{:<24} p50 {:>10.3?}  p90 {:>10.3?}  p99 {:>10.3?}  max {:>10.3?}",
+        label,
+        percentile(0.5),
+        percentile(0.9),
+        percentile(0.99),
+        samples[samples.len() - 1]
+    );
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+    runtime.block_on(async {
+        let router = common::config(0).build().unwrap();
+        let cookie = sign_in(&router).await;
+        report("userinfo idle", &measure(&router, &cookie).await);
+
+        let workers = std::thread::available_parallelism().map_or(1, |n| n.get()) * 4;
+        let load: Vec<_> = (0..workers)
+            .map(|_| {
+                let router = router.clone();
+                tokio::spawn(async move {
+                    loop {
+                        let resp = router.clone().oneshot(authenticate("wrong")).await.unwrap();
+                        if resp.status() == StatusCode::SERVICE_UNAVAILABLE {
+                            tokio::time::sleep(Duration::from_millis(10)).await;
+                        }
+                    }
+                })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let label = format!("userinfo with {} logins", workers);
+        report(&label, &measure(&router, &cookie).await);
+        for task in load {
+            task.abort();
+        }
+    });
+}
@@ -1,14 +1,45 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::io::{ErrorKind, IsTerminal};
+use std::path::{Path, PathBuf};
 use std::str::from_utf8;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{anyhow, bail, Context, Result};
+use argon2::Params;
 use clap::{Parser, Subcommand};
-use serde::Deserialize;
-use tokio::io::{stdin, AsyncBufReadExt, BufReader};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use tokio::io::{stdin, AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::sync::watch;
+use url::{Origin, Url};
 
-use crate::service::{hash_password, ServiceConfig};
+use crate::logging::{self, LogFormat};
+use crate::server::probe::{probe, ProbeOptions};
+use crate::server::systemd::{self, ListenEnv};
+use crate::server::tls::{self, CertificateField};
+use crate::server::{shutdown_signal, unix, ListenOptions, Listener};
+use crate::service::audit::journald::{self, JournaldSink};
+use crate::service::audit::syslog::{self, SyslogSink};
+use crate::service::audit::{AuditEvent, AuditEventKind, AuditLog, AuditSink, FileSink};
+use crate::service::auth::{HashAlgorithm, Pepper, Verifier};
+use crate::service::client::ForwardedHeader;
+use crate::service::handoff::HandoffStore;
+use crate::service::hashing::HashingPool;
+use crate::service::health::Draining;
+use crate::service::lockout::{LockoutPolicy, LockoutStore};
+use crate::service::metrics::{metrics_router, Metrics};
+use crate::service::origin::parse_origin;
+use crate::service::password_policy::{generate_password, PasswordPolicy};
+use crate::service::rate_limit::{LoginRateLimits, RateLimit};
+use crate::service::realm::{Realm, RealmTable};
+use crate::service::redirection::RedirectDomain;
+use crate::service::telemetry;
+use crate::service::user_store::UserStore;
+use crate::service::{ConnectionInfo, ServiceConfig, ServiceState, TrustedProxies};
+
+const USERS_FILE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const LOCKOUT_SYNC_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Parser)]
 struct GenKeyArgs {
@@ -16,10 +47,28 @@ struct GenKeyArgs {
     output: Option<PathBuf>,
 }
 
+#[derive(Debug, Clone, Parser)]
+struct HashingArgs {
+    #[clap(short, long)]
+    algorithm: Option<HashAlgorithm>,
+    #[clap(long)]
+    m_cost: Option<u32>,
+    #[clap(long)]
+    t_cost: Option<u32>,
+    #[clap(long)]
+    p_cost: Option<u32>,
+}
+
 #[derive(Debug, Parser)]
 struct HashArgs {
     #[clap(short, long)]
     input: Option<PathBuf>,
+    #[clap(flatten)]
+    hashing: HashingArgs,
+    #[clap(long, conflicts_with_all = ["input", "batch"])]
+    generate: bool,
+    #[clap(long)]
+    batch: bool,
 }
 
 #[derive(Debug, Parser)]
@@ -32,11 +81,66 @@ struct ServeArgs {
     address: Option<String>,
 }
 
+#[derive(Debug, Parser)]
+struct HealthcheckArgs {
+    #[clap(short, long)]
+    address: Option<String>,
+    #[clap(long, default_value = "/readyz")]
+    path: String,
+    #[clap(long, default_value_t = 5)]
+    timeout_secs: u64,
+}
+
+#[derive(Debug, Parser)]
+struct UnlockArgs {
+    #[clap(short, long)]
+    user: String,
+    #[clap(short, long)]
+    realm: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+struct UserNameArgs {
+    username: String,
+}
+
+#[derive(Debug, Parser)]
+struct UserPasswordArgs {
+    username: String,
+    #[clap(short, long)]
+    input: Option<PathBuf>,
+    #[clap(flatten)]
+    hashing: HashingArgs,
+    #[clap(long, conflicts_with = "input")]
+    generate: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum UserCommands {
+    Add(UserPasswordArgs),
+    Remove(UserNameArgs),
+    Passwd(UserPasswordArgs),
+    List,
+    Disable(UserNameArgs),
+    Enable(UserNameArgs),
+}
+
+#[derive(Debug, Parser)]
+struct UserArgs {
+    #[clap(subcommand)]
+    command: UserCommands,
+    #[clap(short = 'f', long, global = true)]
+    users_file: Option<PathBuf>,
+}
+
 #[derive(Debug, Subcommand)]
 enum Commands {
     GenKey(GenKeyArgs),
     Hash(HashArgs),
     Serve(ServeArgs),
+    Healthcheck(HealthcheckArgs),
+    Unlock(UnlockArgs),
+    User(UserArgs),
 }
 
 #[derive(Debug, Parser)]
@@ -47,26 +151,143 @@ pub struct Args {
     config: Option<PathBuf>,
 }
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 struct User {
     username: String,
     password: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    disabled: bool,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct UserList {
+    #[serde(default)]
+    users: Vec<User>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum RealmUser {
+    Reference(String),
+    Inline(User),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct RealmSetting {
+    name: String,
+    hosts: Option<Vec<String>>,
+    path_prefix: Option<String>,
+    cookie_name: Option<String>,
+    session_absolute_timeout_hours: Option<u64>,
+    title: Option<String>,
+    allowed_redirect_domains: Option<Vec<String>>,
+    client_certificate_auth: Option<bool>,
+    users: Option<Vec<RealmUser>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PepperSetting {
+    id: String,
+    file: PathBuf,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PasswordHashingSetting {
+    algorithm: Option<String>,
+    m_cost: Option<u32>,
+    t_cost: Option<u32>,
+    p_cost: Option<u32>,
+    rehash_on_login: Option<bool>,
+    peppers: Option<Vec<PepperSetting>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PasswordPolicySetting {
+    min_length: Option<usize>,
+    min_strength: Option<u8>,
+    breached_passwords_dir: Option<PathBuf>,
 }
 
 #[derive(Debug, Default, Deserialize)]
 struct Setting {
+    log_format: Option<LogFormat>,
     session_absolute_timeout_hours: Option<u64>,
     session_secret_key_file: Option<PathBuf>,
     session_secret_key: Option<String>,
     address: Option<String>,
+    unix_socket_mode: Option<String>,
+    unix_socket_owner: Option<String>,
+    proxy_protocol: Option<bool>,
+    trusted_proxies: Option<Vec<IpNet>>,
+    trust_unix_socket: Option<bool>,
+    forwarded_header: Option<ForwardedHeader>,
+    allowed_origins: Option<Vec<String>>,
+    allowed_redirect_domains: Option<Vec<String>>,
+    sso_url: Option<Url>,
+    sso_handoff_timeout_secs: Option<u64>,
+    metrics: Option<bool>,
+    metrics_address: Option<String>,
+    audit_log_file: Option<PathBuf>,
+    audit_log_max_size_mb: Option<u64>,
+    audit_log_max_files: Option<usize>,
+    audit_syslog: Option<bool>,
+    audit_syslog_socket: Option<PathBuf>,
+    audit_journald: Option<bool>,
+    audit_journald_socket: Option<PathBuf>,
+    otlp_endpoint: Option<Url>,
+    otlp_service_name: Option<String>,
+    shutdown_delay_secs: Option<u64>,
+    shutdown_grace_period_secs: Option<u64>,
+    rate_limit_address_burst: Option<u32>,
+    rate_limit_address_per_minute: Option<u32>,
+    rate_limit_username_burst: Option<u32>,
+    rate_limit_username_per_minute: Option<u32>,
+    lockout_max_failures: Option<u32>,
+    lockout_duration_mins: Option<u64>,
+    lockout_state_file: Option<PathBuf>,
+    password_hashing_concurrency: Option<usize>,
+    password_hashing_queue_size: Option<usize>,
+    password_hashing_queue_timeout_ms: Option<u64>,
+    password_hashing: Option<PasswordHashingSetting>,
+    password_policy: Option<PasswordPolicySetting>,
+    tls_certificate_file: Option<PathBuf>,
+    tls_private_key_file: Option<PathBuf>,
+    tls_client_ca_file: Option<PathBuf>,
+    tls_client_certificate_field: Option<CertificateField>,
+    client_certificate_auth: Option<bool>,
+    #[serde(default)]
     users: Vec<User>,
+    users_file: Option<PathBuf>,
+    realms: Option<Vec<RealmSetting>>,
 }
 
 struct ServeOptions {
-    session_absolute_timeout_hours: u64,
     session_secret_key: Vec<u8>,
     address: String,
-    users: HashMap<String, String>,
+    unix_socket_mode: Option<u32>,
+    unix_socket_owner: Option<String>,
+    proxy_protocol: bool,
+    trusted_proxies: TrustedProxies,
+    allowed_origins: Vec<Origin>,
+    sso_url: Option<Url>,
+    sso_handoff_timeout_secs: u64,
+    metrics: bool,
+    metrics_address: Option<String>,
+    audit: AuditLog,
+    otlp_endpoint: Option<Url>,
+    otlp_service_name: String,
+    shutdown_delay: Duration,
+    shutdown_grace_period: Duration,
+    rate_limits: LoginRateLimits,
+    lockout: LockoutStore,
+    hashing: HashingPool,
+    verifier: Verifier,
+    user_store: Option<Arc<UserStore>>,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    client_certificate_field: CertificateField,
+    realms: RealmTable,
+    realm_builder: RealmBuilder,
+    users_file: Option<PathBuf>,
 }
 
 async fn read_key(path: PathBuf) -> Result<Vec<u8>> {
@@ -76,8 +297,280 @@ async fn read_key(path: PathBuf) -> Result<Vec<u8>> {
     hex::decode(text).context("invalid hex string in key file")
 }
 
+fn parse_redirect_domains(domains: Vec<String>) -> Result<Vec<RedirectDomain>> {
+    domains.iter().map(|d| Ok(d.parse()?)).collect()
+}
+
+fn hours(hours: u64) -> Duration {
+    Duration::from_secs(60 * 60 * hours)
+}
+
+fn audit_log(setting: &Setting) -> Result<AuditLog> {
+    let mut sinks: Vec<Box<dyn AuditSink>> = vec![];
+    if let Some(path) = &setting.audit_log_file {
+        let max_size = setting.audit_log_max_size_mb.unwrap_or(100) * 1024 * 1024;
+        let max_files = setting.audit_log_max_files.unwrap_or(5);
+        let sink = FileSink::open(path.clone(), max_size, max_files)
+            .context("could not open audit log")?;
+        sinks.push(Box::new(sink));
+    }
+    if setting.audit_syslog.unwrap_or(setting.audit_syslog_socket.is_some()) {
+        let path = setting.audit_syslog_socket.clone().unwrap_or(syslog::DEFAULT_SOCKET.into());
+        let sink = SyslogSink::new(path).context("could not create syslog socket")?;
+        sinks.push(Box::new(sink));
+    }
+    if setting.audit_journald.unwrap_or(setting.audit_journald_socket.is_some()) {
+        let path = setting.audit_journald_socket.clone().unwrap_or(journald::DEFAULT_SOCKET.into());
+        let sink = JournaldSink::new(path).context("could not create journald socket")?;
+        sinks.push(Box::new(sink));
+    }
+    Ok(AuditLog::new(sinks))
+}
+
+fn lockout_store(setting: &Setting) -> LockoutStore {
+    let policy = LockoutPolicy {
+        max_failures: setting.lockout_max_failures.unwrap_or(0),
+        duration: Duration::from_secs(setting.lockout_duration_mins.unwrap_or(15) * 60),
+    };
+    LockoutStore::new(policy, setting.lockout_state_file.clone())
+}
+
+fn hashing_pool(setting: &Setting) -> Result<HashingPool> {
+    let default = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let concurrency = setting.password_hashing_concurrency.unwrap_or(default);
+    if concurrency == 0 {
+        bail!("password hashing concurrency must be positive");
+    }
+    let queue_size = setting.password_hashing_queue_size.unwrap_or(concurrency * 4);
+    let queue_timeout = setting.password_hashing_queue_timeout_ms.unwrap_or(1000);
+    Ok(HashingPool::new(concurrency, queue_size, Duration::from_millis(queue_timeout)))
+}
+
+fn hash_algorithm(setting: &PasswordHashingSetting) -> Result<HashAlgorithm> {
+    let algorithm: HashAlgorithm = setting.algorithm.as_deref().unwrap_or("argon2id").parse()?;
+    if !algorithm.is_argon2() {
+        bail!("password hashing algorithm must be argon2id, argon2i or argon2d");
+    }
+    Ok(algorithm)
+}
+
+fn argon2_params(
+    setting: &PasswordHashingSetting,
+    m_cost: Option<u32>,
+    t_cost: Option<u32>,
+    p_cost: Option<u32>,
+) -> Result<Params> {
+    Params::new(
+        m_cost.or(setting.m_cost).unwrap_or(Params::DEFAULT_M_COST),
+        t_cost.or(setting.t_cost).unwrap_or(Params::DEFAULT_T_COST),
+        p_cost.or(setting.p_cost).unwrap_or(Params::DEFAULT_P_COST),
+        None,
+    )
+    .map_err(|err| anyhow!("invalid password hashing parameters: {}", err))
+}
+
+// The first pepper is used for new hashes.
+async fn read_peppers(setting: &PasswordHashingSetting) -> Result<Vec<Pepper>> {
+    let mut peppers: Vec<Pepper> = vec![];
+    for pepper in setting.peppers.iter().flatten() {
+        if setting.peppers.iter().flatten().filter(|p| p.id == pepper.id).count() > 1 {
+            bail!("duplicate pepper id '{}'", pepper.id);
+        }
+        let secret = read_key(pepper.file.clone()).await.context("could not parse pepper file")?;
+        peppers.push(Pepper::new(&pepper.id, secret)?);
+    }
+    Ok(peppers)
+}
+
+fn password_policy(setting: Option<PasswordPolicySetting>) -> Result<PasswordPolicy> {
+    let setting = setting.unwrap_or_default();
+    let default = PasswordPolicy::default();
+    let min_strength = setting.min_strength.unwrap_or(default.min_strength);
+    if min_strength > 4 {
+        bail!("minimum password strength must be between 0 and 4");
+    }
+    Ok(PasswordPolicy {
+        min_length: setting.min_length.unwrap_or(default.min_length),
+        min_strength,
+        breached_passwords_dir: setting.breached_passwords_dir,
+    })
+}
+
+fn rate_limit(burst: u32, per_minute: u32) -> Result<RateLimit> {
+    if burst > 0 && per_minute == 0 {
+        bail!("rate limit must refill at least once per minute");
+    }
+    Ok(RateLimit { burst, per_minute })
+}
+
+fn build_realm(realm: RealmSetting, default: &Realm, disabled: &HashSet<String>) -> Result<Realm> {
+    if realm.hosts.is_none() && realm.path_prefix.is_none() {
+        bail!("realm '{}' needs hosts or path_prefix", realm.name);
+    }
+    if let Some(prefix) = &realm.path_prefix {
+        if !prefix.starts_with('/') || prefix.ends_with('/') {
+            bail!("path_prefix of realm '{}' must start and not end with '/'", realm.name);
+        }
+    }
+    let users = match realm.users {
+        Some(users) => users
+            .into_iter()
+            .filter_map(|u| match u {
+                RealmUser::Reference(name) => match default.users.get(&name) {
+                    Some(password) => Some(Ok((name, password.clone()))),
+                    None if disabled.contains(&name) => None,
+                    None => Some(Err(anyhow!(
+                        "realm '{}' refers to unknown user '{}'",
+                        realm.name,
+                        name
+                    ))),
+                },
+                RealmUser::Inline(u) if u.disabled => None,
+                RealmUser::Inline(u) => Some(Ok((u.username, u.password))),
+            })
+            .collect::<Result<_>>()?,
+        None => default.users.clone(),
+    };
+    let allowed_redirect_domains = match realm.allowed_redirect_domains {
+        Some(domains) => parse_redirect_domains(domains)?,
+        None => default.allowed_redirect_domains.clone(),
+    };
+    Ok(Realm {
+        cookie_name: realm.cookie_name.unwrap_or_else(|| format!("session_{}", realm.name)),
+        name: realm.name,
+        hosts: realm.hosts.unwrap_or_default(),
+        path_prefix: realm.path_prefix,
+        session_absolute_timeout: realm
+            .session_absolute_timeout_hours
+            .map(hours)
+            .unwrap_or(default.session_absolute_timeout),
+        title: realm.title,
+        allowed_redirect_domains,
+        // Every realm trusts the same client certificate authority, so realms opt in to it.
+        client_certificate_auth: realm.client_certificate_auth.unwrap_or(false),
+        users,
+    })
+}
+
+// Realms are rebuilt from their settings whenever the users file changes.
+#[derive(Clone)]
+struct RealmBuilder {
+    default: Realm,
+    realms: Vec<RealmSetting>,
+    users: Vec<User>,
+}
+
+impl RealmBuilder {
+    fn build(&self, file_users: Vec<User>) -> Result<RealmTable> {
+        let mut default = self.default.clone();
+        let mut disabled = HashSet::new();
+        for user in self.users.iter().cloned().chain(file_users) {
+            if default.users.contains_key(&user.username) || disabled.contains(&user.username) {
+                bail!("duplicate user '{}'", user.username);
+            }
+            if user.disabled {
+                disabled.insert(user.username);
+            } else {
+                default.users.insert(user.username, user.password);
+            }
+        }
+        let mut realms = vec![];
+        for realm in self.realms.iter().cloned() {
+            if realms.iter().any(|r: &Realm| r.name == realm.name) {
+                bail!("duplicate realm '{}'", realm.name);
+            }
+            let realm = build_realm(realm, &default, &disabled)?;
+            // A session cookie does not name its realm, so realms sharing a cookie would accept
+            // each other's sessions.
+            if realms.iter().chain([&default]).any(|r| r.cookie_name == realm.cookie_name) {
+                bail!("realm '{}' shares its cookie name with another realm", realm.name);
+            }
+            realms.push(realm);
+        }
+        Ok(RealmTable::new(realms, default))
+    }
+}
+
+// A missing users file has no users yet, as it is created by the first `user add`.
+async fn read_users_file(path: &Path) -> Result<Vec<User>> {
+    let content = match tokio::fs::read_to_string(path).await {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err).context("could not read users file"),
+    };
+    let users: UserList = toml::from_str(&content).context("could not parse users file")?;
+    Ok(users.users)
+}
+
+async fn modified_time(path: &Path) -> Option<SystemTime> {
+    tokio::fs::metadata(path).await.and_then(|m| m.modified()).ok()
+}
+
+async fn reload_users(path: &Path, builder: &RealmBuilder, state: &ServiceState) -> Result<()> {
+    let realms = builder.build(read_users_file(path).await?)?;
+    state.update(|config| ServiceConfig { realms: realms.clone(), ..config.clone() })?;
+    Ok(())
+}
+
+// Polls the users file, so that changes made by the `user` commands take effect without a
+// restart. An invalid users file is reported and the previous users are kept.
+fn spawn_users_file_watch(path: PathBuf, builder: RealmBuilder, state: ServiceState) {
+    tokio::spawn(async move {
+        let mut modified = modified_time(&path).await;
+        let mut interval = tokio::time::interval(USERS_FILE_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            let current = modified_time(&path).await;
+            if current == modified {
+                continue;
+            }
+            modified = current;
+            match reload_users(&path, &builder, &state).await {
+                Ok(()) => log::info!("reloaded users from {}", path.display()),
+                Err(err) => log::error!("could not reload users: {:#}", err),
+            }
+        }
+    });
+}
+
+async fn sync_lockout(lockout: &LockoutStore) -> Result<()> {
+    let lockout = lockout.clone();
+    tokio::task::spawn_blocking(move || lockout.sync())
+        .await
+        .context("lockout sync task failed")?
+        .context("could not sync lockout state")
+}
+
+fn spawn_lockout_sync(lockout: LockoutStore) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(LOCKOUT_SYNC_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(err) = sync_lockout(&lockout).await {
+                log::error!("{:#}", err);
+            }
+        }
+    });
+}
+
 impl ServeOptions {
     async fn new(args: ServeArgs, setting: Setting) -> Result<Self> {
+        let audit = audit_log(&setting)?;
+        let lockout = lockout_store(&setting);
+        sync_lockout(&lockout).await?;
+        let hashing = hashing_pool(&setting)?;
+        let password_hashing = setting.password_hashing.unwrap_or_default();
+        let params = argon2_params(&password_hashing, None, None, None)?;
+        let peppers = read_peppers(&password_hashing).await?;
+        let verifier = Verifier::new(hash_algorithm(&password_hashing)?, params, peppers)
+            .context("could not compute dummy password hash")?;
+        // Only users of the users file are rehashed, so that the config file is never rewritten.
+        let users_file = setting.users_file;
+        let user_store = match (password_hashing.rehash_on_login, &users_file) {
+            (Some(true), Some(path)) => Some(Arc::new(UserStore::new(path.clone()))),
+            (Some(true), None) => bail!("rehash_on_login requires a users_file"),
+            _ => None,
+        };
         let session_absolute_timeout_hours = args
             .session_absolute_timeout_hours
             .or(setting.session_absolute_timeout_hours)
@@ -89,26 +582,237 @@ impl ServeOptions {
             (None, Some(key)) => key.into_bytes(),
             _ => bail!("session secret key is required"),
         };
-        let users: HashMap<String, String> =
-            HashMap::from_iter(setting.users.into_iter().map(|u| (u.username, u.password)));
         let address = args.address.or(setting.address).unwrap_or("127.0.0.1:8080".into());
+        let unix_socket_mode = setting
+            .unix_socket_mode
+            .map(|m| unix::parse_mode(&m))
+            .transpose()
+            .context("could not parse unix socket mode")?;
+        let unix_socket_owner = setting.unix_socket_owner;
+        let proxy_protocol = setting.proxy_protocol.unwrap_or(false);
+        let trusted_proxies = TrustedProxies {
+            networks: setting.trusted_proxies.unwrap_or_default(),
+            header: setting.forwarded_header.unwrap_or_default(),
+            unix_socket: setting.trust_unix_socket.unwrap_or(false),
+        };
+        let allowed_origins = setting
+            .allowed_origins
+            .unwrap_or_default()
+            .iter()
+            .map(|o| parse_origin(o).ok_or_else(|| anyhow!("invalid allowed origin '{}'", o)))
+            .collect::<Result<_>>()?;
+        let default_realm = Realm {
+            name: "default".into(),
+            hosts: vec![],
+            path_prefix: None,
+            cookie_name: "session".into(),
+            session_absolute_timeout: hours(session_absolute_timeout_hours),
+            title: None,
+            allowed_redirect_domains: parse_redirect_domains(
+                setting.allowed_redirect_domains.unwrap_or_default(),
+            )?,
+            client_certificate_auth: setting.client_certificate_auth.unwrap_or(true),
+            users: HashMap::new(),
+        };
+        let realm_builder = RealmBuilder {
+            default: default_realm,
+            realms: setting.realms.unwrap_or_default(),
+            users: setting.users,
+        };
+        let file_users = match &users_file {
+            Some(path) => read_users_file(path).await?,
+            None => vec![],
+        };
+        let realms = realm_builder.build(file_users)?;
+        for realm in realms.iter() {
+            let weak = realm.users.values().filter(|hash| verifier.needs_rehash(hash)).count();
+            if weak > 0 {
+                log::warn!(
+                    "{} users in realm '{}' have password hashes that differ from the configured \
+                     algorithm, which lets their existence be detected by timing until they are \
+                     rehashed",
+                    weak,
+                    realm.name
+                );
+            }
+        }
+        // Relative urls are resolved against the sso url, which would otherwise drop its last
+        // path segment.
+        let sso_url = setting.sso_url.map(|mut url| {
+            if !url.path().ends_with('/') {
+                url.set_path(&format!("{}/", url.path()));
+            }
+            url
+        });
+        let sso_handoff_timeout_secs = setting.sso_handoff_timeout_secs.unwrap_or(30);
+        let metrics_address = setting.metrics_address;
+        let metrics = setting.metrics.unwrap_or(metrics_address.is_some());
+        let otlp_endpoint = match setting.otlp_endpoint {
+            Some(endpoint) => {
+                Some(telemetry::parse_endpoint(&endpoint).context("invalid otlp endpoint")?)
+            }
+            None => None,
+        };
+        let otlp_service_name = setting.otlp_service_name.unwrap_or("staticauth".into());
+        let shutdown_delay = Duration::from_secs(setting.shutdown_delay_secs.unwrap_or(0));
+        let shutdown_grace_period =
+            Duration::from_secs(setting.shutdown_grace_period_secs.unwrap_or(30));
+        let address_limit = rate_limit(
+            setting.rate_limit_address_burst.unwrap_or(20),
+            setting.rate_limit_address_per_minute.unwrap_or(10),
+        )?;
+        let username_limit = rate_limit(
+            setting.rate_limit_username_burst.unwrap_or(0),
+            setting.rate_limit_username_per_minute.unwrap_or(5),
+        )?;
+        let rate_limits = LoginRateLimits::new(address_limit, username_limit);
+        let tls = match (setting.tls_certificate_file, setting.tls_private_key_file) {
+            (Some(cert), Some(key)) => Some(
+                tls::load_server_config(&cert, &key, setting.tls_client_ca_file.as_deref())
+                    .await
+                    .context("could not load tls configuration")?,
+            ),
+            (None, None) if setting.tls_client_ca_file.is_some() => {
+                bail!("client certificate authentication requires tls")
+            }
+            (None, None) => None,
+            _ => bail!("both tls certificate and private key are required"),
+        };
+        let client_certificate_field = setting.tls_client_certificate_field.unwrap_or_default();
 
-        Ok(Self { session_absolute_timeout_hours, session_secret_key, address, users })
+        Ok(Self {
+            session_secret_key,
+            address,
+            unix_socket_mode,
+            unix_socket_owner,
+            proxy_protocol,
+            trusted_proxies,
+            allowed_origins,
+            sso_url,
+            sso_handoff_timeout_secs,
+            metrics,
+            metrics_address,
+            audit,
+            otlp_endpoint,
+            otlp_service_name,
+            shutdown_delay,
+            shutdown_grace_period,
+            rate_limits,
+            lockout,
+            hashing,
+            verifier,
+            user_store,
+            tls,
+            client_certificate_field,
+            realms,
+            realm_builder,
+            users_file,
+        })
     }
 
-    async fn run(self) -> Result<()> {
+    async fn run(self, listen: ListenEnv) -> Result<()> {
+        let exporter = match self.otlp_endpoint {
+            Some(endpoint) => Some(telemetry::init(&endpoint, self.otlp_service_name)?),
+            None => None,
+        };
         let config = ServiceConfig {
-            session_absolute_timeout: Duration::from_secs(
-                60 * 60 * self.session_absolute_timeout_hours,
-            ),
             session_secret_key: self.session_secret_key,
-            users: self.users,
+            trusted_proxies: self.trusted_proxies,
+            allowed_origins: self.allowed_origins,
+            sso_url: self.sso_url,
+            handoff_codes: HandoffStore::new(Duration::from_secs(self.sso_handoff_timeout_secs)),
+            realms: self.realms,
+            metrics: Arc::new(Metrics::new()),
+            audit: Arc::new(self.audit),
+            draining: Draining::default(),
+            rate_limits: self.rate_limits,
+            lockout: self.lockout.clone(),
+            hashing: self.hashing,
+            verifier: self.verifier,
+            user_store: self.user_store,
+        };
+        let draining = config.draining.clone();
+        let metrics = metrics_router(config.metrics.clone());
+        let state = ServiceState::new(config)?;
+        if let Some(path) = self.users_file {
+            spawn_users_file_watch(path, self.realm_builder, state.clone());
+        }
+        if self.lockout.is_persistent() {
+            spawn_lockout_sync(self.lockout.clone());
+        }
+        let mut service = state.build();
+        let (stopping_sender, stopping) = watch::channel(());
+        match (self.metrics, self.metrics_address) {
+            (false, _) => {}
+            (true, None) => service = service.merge(metrics),
+            (true, Some(address)) => {
+                let listen_options = ListenOptions {
+                    address,
+                    unix_socket_mode: None,
+                    unix_socket_owner: None,
+                    proxy_protocol: false,
+                    socket_activation: None,
+                    tls: None,
+                    client_certificate_field: Default::default(),
+                };
+                let listener = Listener::bind(listen_options).await?;
+                let mut stopping = stopping.clone();
+                let server = axum::Server::builder(listener)
+                    .serve(metrics.into_make_service())
+                    .with_graceful_shutdown(async move {
+                        let _ = stopping.changed().await;
+                    });
+                tokio::spawn(async move {
+                    if let Err(err) = server.await {
+                        log::error!("error while running metrics server: {}", err);
+                    }
+                });
+            }
+        }
+
+        let listen_options = ListenOptions {
+            address: self.address,
+            unix_socket_mode: self.unix_socket_mode,
+            unix_socket_owner: self.unix_socket_owner,
+            proxy_protocol: self.proxy_protocol,
+            socket_activation: Some(listen),
+            tls: self.tls,
+            client_certificate_field: self.client_certificate_field,
         };
-        let service = config.build();
+        let listener = Listener::bind(listen_options).await?;
+        let shutdown_delay = self.shutdown_delay;
+        let server = axum::Server::builder(listener)
+            .serve(service.into_make_service_with_connect_info::<ConnectionInfo>())
+            .with_graceful_shutdown(async move {
+                shutdown_signal().await;
+                systemd::notify("STOPPING=1");
+                draining.start();
+                // Keep accepting while load balancers observe the failing readiness check.
+                tokio::time::sleep(shutdown_delay).await;
+                log::info!("stopped accepting connections, draining in-flight requests");
+                let _ = stopping_sender.send(());
+            });
 
-        let address = self.address.parse().context("could not parse address")?;
-        let server = axum::Server::bind(&address);
-        server.serve(service.into_make_service()).await.context("error while running server")?;
+        systemd::notify("READY=1");
+        systemd::spawn_watchdog();
+        let mut stopping = stopping.clone();
+        let grace_period = async {
+            if stopping.changed().await.is_err() {
+                return std::future::pending().await;
+            }
+            tokio::time::sleep(self.shutdown_grace_period).await;
+        };
+        tokio::select! {
+            result = server => result.context("error while running server")?,
+            _ = grace_period => log::warn!("grace period elapsed, closing remaining connections"),
+        }
+        if let Err(err) = sync_lockout(&self.lockout).await {
+            log::error!("{:#}", err);
+        }
+        if let Some(exporter) = exporter {
+            exporter.shutdown().await;
+        }
+        log::info!("shutdown complete");
         Ok(())
     }
 }
@@ -138,36 +842,300 @@ impl GenKeyOptions {
     }
 }
 
+// Hashes new passwords with the configured algorithm and pepper once they meet the policy.
+struct PasswordHasher {
+    algorithm: HashAlgorithm,
+    params: Params,
+    pepper: Option<Pepper>,
+    policy: PasswordPolicy,
+}
+
+impl PasswordHasher {
+    async fn new(args: HashingArgs, setting: Setting) -> Result<Self> {
+        let password_hashing = setting.password_hashing.unwrap_or_default();
+        let algorithm = match args.algorithm {
+            Some(algorithm) => algorithm,
+            None => hash_algorithm(&password_hashing)?,
+        };
+        let costs = [args.m_cost, args.t_cost, args.p_cost];
+        if !algorithm.is_argon2() && costs.iter().any(Option::is_some) {
+            bail!("--m-cost, --t-cost and --p-cost only apply to argon2");
+        }
+        let params = argon2_params(&password_hashing, args.m_cost, args.t_cost, args.p_cost)?;
+        let pepper = match algorithm.is_argon2() {
+            true => read_peppers(&password_hashing).await?.into_iter().next(),
+            false => None,
+        };
+        let policy = password_policy(setting.password_policy)?;
+        Ok(Self { algorithm, params, pepper, policy })
+    }
+
+    fn hash(&self, password: &str) -> Result<String> {
+        self.hash_with_policy(password, &self.policy)
+    }
+
+    // Generated passwords are random, so they are not looked up in the breached passwords,
+    // whose range files may not be available where passwords are generated.
+    fn hash_generated(&self, password: &str) -> Result<String> {
+        let policy = PasswordPolicy { breached_passwords_dir: None, ..self.policy.clone() };
+        self.hash_with_policy(password, &policy)
+    }
+
+    fn hash_with_policy(&self, password: &str, policy: &PasswordPolicy) -> Result<String> {
+        policy.check(password).context("password does not meet the password policy")?;
+        Ok(self.algorithm.hash(password, &self.params, self.pepper.as_ref())?)
+    }
+}
+
+// Passwords typed on a terminal are read without echo and must be entered twice.
+async fn read_password(input: Option<&Path>) -> Result<String> {
+    if let Some(path) = input {
+        let content = tokio::fs::read_to_string(path).await.context("could not read input file")?;
+        return Ok(content.trim().to_owned());
+    }
+    if std::io::stdin().is_terminal() {
+        let password =
+            rpassword::prompt_password("Password: ").context("could not read password")?;
+        let confirmation =
+            rpassword::prompt_password("Confirm password: ").context("could not read password")?;
+        if password != confirmation {
+            bail!("passwords do not match");
+        }
+        return Ok(password);
+    }
+    BufReader::new(stdin())
+        .lines()
+        .next_line()
+        .await
+        .context("could not read from stdin")?
+        .ok_or(anyhow!("empty line"))
+}
+
 struct HashOptions {
     input: Option<PathBuf>,
+    hasher: PasswordHasher,
+    generate: bool,
+    batch: bool,
 }
 
 impl HashOptions {
-    async fn new(args: HashArgs, _setting: Setting) -> Result<Self> {
-        Ok(Self { input: args.input })
+    async fn new(args: HashArgs, setting: Setting) -> Result<Self> {
+        let hasher = PasswordHasher::new(args.hashing, setting).await?;
+        Ok(Self { input: args.input, hasher, generate: args.generate, batch: args.batch })
+    }
+
+    // Hashes `username:password` lines into `[[users]]` entries.
+    fn hash_batch(&self, input: &str) -> Result<String> {
+        let mut users = vec![];
+        for (i, line) in input.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let Some((username, password)) = line.split_once(':').filter(|(u, _)| !u.is_empty())
+            else {
+                bail!("line {} is not in username:password format", i + 1);
+            };
+            let password = self
+                .hasher
+                .hash(password)
+                .with_context(|| format!("could not hash password of user '{}'", username))?;
+            users.push(User { username: username.into(), password, disabled: false });
+        }
+        toml::to_string(&UserList { users }).context("could not format users")
+    }
+
+    // Passwords are not read from a terminal in batch mode, where they would be echoed.
+    async fn run_batch(&self) -> Result<()> {
+        let input = match &self.input {
+            Some(path) => {
+                tokio::fs::read_to_string(path).await.context("could not read input file")?
+            }
+            None if std::io::stdin().is_terminal() => {
+                bail!("--batch reads passwords from --input or a pipe, not from a terminal")
+            }
+            None => {
+                let mut input = String::new();
+                stdin().read_to_string(&mut input).await.context("could not read from stdin")?;
+                input
+            }
+        };
+        print!("{}", self.hash_batch(&input)?);
+        Ok(())
     }
 
     async fn run(self) -> Result<()> {
-        let input: String = match self.input {
-            Some(path) => tokio::fs::read_to_string(path)
-                .await
-                .context("could not read input file")?
-                .trim()
-                .to_owned(),
-            None => BufReader::new(stdin())
-                .lines()
-                .next_line()
-                .await
-                .context("could not read from stdin")?
-                .ok_or(anyhow!("empty line"))?,
-        };
-        let hash = hash_password(&input)?;
+        if self.batch {
+            return self.run_batch().await;
+        }
+        let hash = match self.generate {
+            true => {
+                let password = generate_password();
+                let hash = self.hasher.hash_generated(&password)?;
+                eprintln!("generated password: {}", password);
+                hash
+            }
+            false => self.hasher.hash(&read_password(self.input.as_deref()).await?)?,
+        };
         println!("{}", hash);
         Ok(())
     }
 }
 
-pub async fn run(args: Args) -> Result<()> {
+struct HealthcheckOptions {
+    address: String,
+    path: String,
+    timeout: Duration,
+    probe: ProbeOptions,
+}
+
+impl HealthcheckOptions {
+    async fn new(args: HealthcheckArgs, setting: Setting) -> Result<Self> {
+        let address = args.address.or(setting.address).unwrap_or("127.0.0.1:8080".into());
+        let timeout = Duration::from_secs(args.timeout_secs);
+        // The server's own certificate is trusted, as it is usually not issued for the address
+        // the probe connects to.
+        let tls = match &setting.tls_certificate_file {
+            Some(path) => Some(
+                tls::load_pinned_client_config(path)
+                    .await
+                    .context("could not load tls configuration")?,
+            ),
+            None => None,
+        };
+        let probe = ProbeOptions { proxy_protocol: setting.proxy_protocol.unwrap_or(false), tls };
+        Ok(Self { address, path: args.path, timeout, probe })
+    }
+
+    async fn run(self) -> Result<()> {
+        let status = probe(&self.address, &self.path, self.timeout, &self.probe)
+            .await
+            .with_context(|| format!("could not probe {}", self.address))?;
+        if !status.is_success() {
+            bail!("{} responded with {}", self.path, status);
+        }
+        println!("{} {}", self.path, status);
+        Ok(())
+    }
+}
+
+struct UnlockOptions {
+    user: String,
+    realm: Option<String>,
+    lockout: LockoutStore,
+    audit: AuditLog,
+}
+
+impl UnlockOptions {
+    async fn new(args: UnlockArgs, setting: Setting) -> Result<Self> {
+        if setting.lockout_state_file.is_none() {
+            bail!("lockout_state_file is not configured");
+        }
+        let lockout = lockout_store(&setting);
+        let audit = audit_log(&setting)?;
+        Ok(Self { user: args.user, realm: args.realm, lockout, audit })
+    }
+
+    async fn run(self) -> Result<()> {
+        let unlocked = self
+            .lockout
+            .unlock(self.realm.as_deref(), &self.user)
+            .context("could not update lockout state")?;
+        if unlocked.is_empty() {
+            println!("user '{}' is not locked", self.user);
+        }
+        for realm in unlocked {
+            self.audit.record(&AuditEvent {
+                user: Some(&self.user),
+                reason: Some("admin"),
+                ..AuditEvent::new(AuditEventKind::AccountUnlocked, &realm)
+            });
+            println!("unlocked user '{}' in realm '{}'", self.user, realm);
+        }
+        Ok(())
+    }
+}
+
+struct UserOptions {
+    command: UserCommands,
+    store: UserStore,
+    hasher: Option<PasswordHasher>,
+}
+
+impl UserOptions {
+    async fn new(args: UserArgs, setting: Setting) -> Result<Self> {
+        let Some(path) = args.users_file.or_else(|| setting.users_file.clone()) else {
+            bail!("users_file is not configured");
+        };
+        let hasher = match &args.command {
+            UserCommands::Add(a) | UserCommands::Passwd(a) => {
+                Some(PasswordHasher::new(a.hashing.clone(), setting).await?)
+            }
+            _ => None,
+        };
+        Ok(Self { command: args.command, store: UserStore::new(path), hasher })
+    }
+
+    fn exists(&self, username: &str) -> Result<bool> {
+        let users = self.store.list_users().context("could not read users file")?;
+        Ok(users.iter().any(|u| u.username == username))
+    }
+
+    async fn hash_password(&self, args: &UserPasswordArgs) -> Result<String> {
+        let hasher = self.hasher.as_ref().expect("hasher is created for password commands");
+        if !args.generate {
+            return hasher.hash(&read_password(args.input.as_deref()).await?);
+        }
+        let password = generate_password();
+        let hash = hasher.hash_generated(&password)?;
+        eprintln!("generated password: {}", password);
+        Ok(hash)
+    }
+
+    async fn run(self) -> Result<()> {
+        let context = "could not update users file";
+        let (username, done) = match &self.command {
+            UserCommands::Add(args) => {
+                if self.exists(&args.username)? {
+                    bail!("user '{}' already exists", args.username);
+                }
+                let hash = self.hash_password(args).await?;
+                if !self.store.add_user(&args.username, &hash).context(context)? {
+                    bail!("user '{}' already exists", args.username);
+                }
+                (&args.username, true)
+            }
+            UserCommands::Remove(args) => {
+                (&args.username, self.store.remove_user(&args.username).context(context)?)
+            }
+            UserCommands::Passwd(args) => {
+                if !self.exists(&args.username)? {
+                    bail!("user '{}' does not exist", args.username);
+                }
+                let hash = self.hash_password(args).await?;
+                (&args.username, self.store.set_password(&args.username, &hash).context(context)?)
+            }
+            UserCommands::List => {
+                let users = self.store.list_users().context("could not read users file")?;
+                for user in users {
+                    match user.disabled {
+                        true => println!("{} (disabled)", user.username),
+                        false => println!("{}", user.username),
+                    }
+                }
+                return Ok(());
+            }
+            UserCommands::Disable(args) => {
+                (&args.username, self.store.set_disabled(&args.username, true).context(context)?)
+            }
+            UserCommands::Enable(args) => {
+                (&args.username, self.store.set_disabled(&args.username, false).context(context)?)
+            }
+        };
+        if !done {
+            bail!("user '{}' does not exist", username);
+        }
+        Ok(())
+    }
+}
+
+pub async fn run(args: Args, listen: ListenEnv) -> Result<()> {
     let setting: Setting = match args.config {
         Some(path) => {
             let content =
@@ -176,10 +1144,105 @@ pub async fn run(args: Args) -> Result<()> {
         }
         None => Default::default(),
     };
+    logging::init(setting.log_format.unwrap_or_default());
 
     match args.command {
         Commands::GenKey(a) => GenKeyOptions::new(a, setting).await?.run().await,
         Commands::Hash(a) => HashOptions::new(a, setting).await?.run().await,
-        Commands::Serve(a) => ServeOptions::new(a, setting).await?.run().await,
+        Commands::Serve(a) => ServeOptions::new(a, setting).await?.run(listen).await,
+        Commands::Healthcheck(a) => HealthcheckOptions::new(a, setting).await?.run().await,
+        Commands::Unlock(a) => UnlockOptions::new(a, setting).await?.run().await,
+        Commands::User(a) => UserOptions::new(a, setting).await?.run().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn hash_options() -> HashOptions {
+        let hasher = PasswordHasher {
+            algorithm: HashAlgorithm::Argon2id,
+            params: Params::new(256, 1, 1, None).unwrap(),
+            pepper: None,
+            policy: PasswordPolicy { min_length: 4, min_strength: 0, breached_passwords_dir: None },
+        };
+        HashOptions { input: None, hasher, generate: false, batch: true }
+    }
+
+    #[test]
+    fn test_hash_batch() {
+        let input = "alice:p@ssw0rd\n\n  \nbob:pass:word\n";
+        let toml = hash_options().hash_batch(input).unwrap();
+        let UserList { users } = toml::from_str(&toml).unwrap();
+
+        let usernames: Vec<_> = users.iter().map(|u| u.username.as_str()).collect();
+        assert_eq!(vec!["alice", "bob"], usernames);
+        assert!(!toml.contains("disabled"), "{}", toml);
+        let users = users.into_iter().map(|u| (u.username, u.password)).collect();
+        let verifier = Verifier::default();
+        assert_eq!(Ok(true), verifier.verify(&users, "alice", "p@ssw0rd"));
+        assert_eq!(Ok(true), verifier.verify(&users, "bob", "pass:word"));
+    }
+
+    #[test]
+    fn test_hash_batch_invalid() {
+        let options = hash_options();
+        let err = options.hash_batch("alice:p@ssw0rd\n\nbob\n").unwrap_err();
+        assert_eq!("line 3 is not in username:password format", err.to_string());
+        let err = options.hash_batch(":p@ssw0rd\n").unwrap_err();
+        assert_eq!("line 1 is not in username:password format", err.to_string());
+        let err = options.hash_batch("alice:abc\n").unwrap_err();
+        assert_eq!("could not hash password of user 'alice'", err.to_string());
+        assert_eq!("users = []\n", options.hash_batch("\n").unwrap());
+    }
+
+    fn users(state: &ServiceState) -> Vec<String> {
+        let mut users: Vec<_> =
+            state.config().realms.select(None, "/").users.keys().cloned().collect();
+        users.sort();
+        users
+    }
+
+    #[tokio::test]
+    async fn test_reload_users() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.toml");
+        let config = ServiceConfig::for_tests([("admin".into(), "hash".into())].into());
+        let default = Realm::clone(&config.realms.select(None, "/"));
+        let inline = User { username: "admin".into(), password: "hash".into(), disabled: false };
+        let builder = RealmBuilder {
+            default: Realm { users: Default::default(), ..default },
+            realms: vec![],
+            users: vec![inline],
+        };
+        let metrics = config.metrics.clone();
+        let state = ServiceState::new(config).unwrap();
+        let _router = state.build();
+        let configured_users = || metrics.configured_users.with_label_values(&["default"]).get();
+        assert_eq!(1, configured_users());
+        assert_eq!(1, metrics.active_keys.get());
+        metrics.active_keys.set(0);
+
+        let content = "[[users]]\nusername = \"alice\"\npassword = \"hash\"\n\n\
+                       [[users]]\nusername = \"bob\"\npassword = \"hash\"\n";
+        fs::write(&path, content).unwrap();
+        reload_users(&path, &builder, &state).await.unwrap();
+        assert_eq!(vec!["admin", "alice", "bob"], users(&state));
+        assert_eq!(3, configured_users());
+        assert_eq!(1, metrics.active_keys.get());
+
+        fs::write(&path, "[[users]]\nusername = \"admin\"\npassword = \"hash\"\n").unwrap();
+        assert!(reload_users(&path, &builder, &state).await.is_err());
+        fs::write(&path, "users = [").unwrap();
+        assert!(reload_users(&path, &builder, &state).await.is_err());
+        assert_eq!(vec!["admin", "alice", "bob"], users(&state));
+
+        fs::remove_file(&path).unwrap();
+        reload_users(&path, &builder, &state).await.unwrap();
+        assert_eq!(vec!["admin"], users(&state));
+        assert_eq!(1, configured_users());
     }
 }
@@ -1,2 +1,4 @@
 pub mod app;
+pub mod logging;
+pub mod server;
 pub mod service;
@@ -0,0 +1,135 @@
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::io::Write;
+
+use chrono::{SecondsFormat, Utc};
+use log::Level;
+use serde::Deserialize;
+
+use crate::service::access_log::current_request_id;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+    Logfmt,
+}
+
+thread_local! {
+    static FIELDS: RefCell<Vec<(&'static str, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+// Attaches structured fields to the records logged by `f` on the current thread.
+pub fn with_fields<F: FnOnce()>(fields: Vec<(&'static str, String)>, f: F) {
+    FIELDS.with(|cell| *cell.borrow_mut() = fields);
+    f();
+    FIELDS.with(|cell| cell.borrow_mut().clear());
+}
+
+fn logfmt_value(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value.chars().all(|c| c.is_ascii_graphic() && !matches!(c, '"' | '=' | '\\'));
+    if plain {
+        value.into()
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+pub fn format_record(
+    format: LogFormat,
+    ts: &str,
+    level: Level,
+    target: &str,
+    message: &str,
+    fields: &[(&str, String)],
+) -> String {
+    let mut line = String::new();
+    match format {
+        LogFormat::Text => {
+            write!(line, "[{} {:<5} {}] {}", ts, level, target, message).unwrap();
+            for (key, value) in fields {
+                write!(line, " {}={}", key, logfmt_value(value)).unwrap();
+            }
+        }
+        LogFormat::Logfmt => {
+            write!(line, "ts={} level={} target={}", ts, level.as_str().to_lowercase(), target)
+                .unwrap();
+            write!(line, " msg={}", logfmt_value(message)).unwrap();
+            for (key, value) in fields {
+                write!(line, " {}={}", key, logfmt_value(value)).unwrap();
+            }
+        }
+        LogFormat::Json => {
+            let json = |s: &str| serde_json::to_string(s).unwrap();
+            write!(line, "{{\"ts\":{},\"level\":{}", json(ts), json(level.as_str())).unwrap();
+            write!(line, ",\"target\":{},\"msg\":{}", json(target), json(message)).unwrap();
+            for (key, value) in fields {
+                write!(line, ",{}:{}", json(key), json(value)).unwrap();
+            }
+            line.push('}');
+        }
+    }
+    line
+}
+
+fn record_fields() -> Vec<(&'static str, String)> {
+    let mut fields = vec![];
+    if let Some(request_id) = current_request_id() {
+        fields.push(("request_id", request_id));
+    }
+    FIELDS.with(|cell| fields.extend(cell.borrow().iter().cloned()));
+    fields
+}
+
+pub fn init(format: LogFormat) {
+    env_logger::Builder::from_default_env()
+        .format(move |buf, record| {
+            let ts = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+            let message = record.args().to_string();
+            let fields = record_fields();
+            let line =
+                format_record(format, &ts, record.level(), record.target(), &message, &fields);
+            writeln!(buf, "{}", line)
+        })
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(format: LogFormat, level: Level, message: &str) -> String {
+        let fields = vec![("request_id", "abc".into()), ("path", "/sign in".into())];
+        format_record(format, "2023-01-01T00:00:00.000Z", level, "staticauth", message, &fields)
+    }
+
+    #[test]
+    fn test_format_text() {
+        let expected = concat!(
+            r#"[2023-01-01T00:00:00.000Z INFO  staticauth] done "#,
+            r#"request_id=abc path="/sign in""#,
+        );
+        assert_eq!(expected, format(LogFormat::Text, Level::Info, "done"));
+    }
+
+    #[test]
+    fn test_format_logfmt() {
+        let expected = concat!(
+            r#"ts=2023-01-01T00:00:00.000Z level=warn target=staticauth msg="not ready" "#,
+            r#"request_id=abc path="/sign in""#,
+        );
+        assert_eq!(expected, format(LogFormat::Logfmt, Level::Warn, "not ready"));
+    }
+
+    #[test]
+    fn test_format_json() {
+        let expected = concat!(
+            r#"{"ts":"2023-01-01T00:00:00.000Z","level":"INFO","target":"staticauth","#,
+            r#""msg":"say \"hi\"","request_id":"abc","path":"/sign in"}"#,
+        );
+        assert_eq!(expected, format(LogFormat::Json, Level::Info, r#"say "hi""#));
+    }
+}
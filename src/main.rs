@@ -1,10 +1,13 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Parser;
 
 use staticauth::app::{run, Args};
+use staticauth::server::systemd::ListenEnv;
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::init();
-    run(Args::parse()).await
+fn main() -> Result<()> {
+    // The environment is only changed here, before the runtime starts any thread.
+    let listen = ListenEnv::take();
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build();
+    let runtime = runtime.context("could not start runtime")?;
+    runtime.block_on(run(Args::parse(), listen))
 }
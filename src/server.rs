@@ -0,0 +1,360 @@
+pub mod probe;
+pub mod proxy_protocol;
+pub mod systemd;
+pub mod tls;
+pub mod unix;
+
+use std::io;
+use std::net::SocketAddr;
+use std::os::fd::OwnedFd;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use axum::extract::connect_info::Connected;
+use hyper::server::accept::Accept;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, Semaphore};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use crate::service::ConnectionInfo;
+
+const ACCEPT_QUEUE_SIZE: usize = 64;
+const UNIX_ADDRESS_PREFIX: &str = "unix:";
+const PROXY_PROTOCOL_TIMEOUT: Duration = Duration::from_secs(5);
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_PENDING_HANDSHAKES: usize = 256;
+const ACCEPT_ERROR_MIN_BACKOFF: Duration = Duration::from_millis(10);
+const ACCEPT_ERROR_MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+macro_rules! delegate_async_io {
+    ($type:ident, $($variant:ident),+) => {
+        impl AsyncRead for $type {
+            fn poll_read(
+                self: Pin<&mut Self>,
+                cx: &mut TaskContext<'_>,
+                buf: &mut ReadBuf<'_>,
+            ) -> Poll<io::Result<()>> {
+                match self.get_mut() {
+                    $($type::$variant(s) => Pin::new(s).poll_read(cx, buf),)+
+                }
+            }
+        }
+
+        impl AsyncWrite for $type {
+            fn poll_write(
+                self: Pin<&mut Self>,
+                cx: &mut TaskContext<'_>,
+                buf: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                match self.get_mut() {
+                    $($type::$variant(s) => Pin::new(s).poll_write(cx, buf),)+
+                }
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+                match self.get_mut() {
+                    $($type::$variant(s) => Pin::new(s).poll_flush(cx),)+
+                }
+            }
+
+            fn poll_shutdown(
+                self: Pin<&mut Self>,
+                cx: &mut TaskContext<'_>,
+            ) -> Poll<io::Result<()>> {
+                match self.get_mut() {
+                    $($type::$variant(s) => Pin::new(s).poll_shutdown(cx),)+
+                }
+            }
+        }
+    };
+}
+
+pub enum Socket {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+delegate_async_io!(Socket, Tcp, Unix);
+
+pub struct Stream {
+    socket: Socket,
+    peer_address: Option<SocketAddr>,
+    client_certificate_names: Vec<String>,
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().socket).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().socket).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().socket).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().socket).poll_shutdown(cx)
+    }
+}
+
+pub enum Connection {
+    Plain(Stream),
+    Tls(Box<TlsStream<Stream>>),
+}
+
+delegate_async_io!(Connection, Plain, Tls);
+
+impl Connected<&Connection> for ConnectionInfo {
+    fn connect_info(target: &Connection) -> Self {
+        let stream = match target {
+            Connection::Plain(s) => s,
+            Connection::Tls(s) => s.get_ref().0,
+        };
+        ConnectionInfo {
+            peer_address: stream.peer_address,
+            client_certificate_names: stream.client_certificate_names.clone(),
+        }
+    }
+}
+
+enum RawListener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl RawListener {
+    async fn accept(&self) -> io::Result<Stream> {
+        match self {
+            RawListener::Tcp(l) => l.accept().await.map(|(s, addr)| Stream {
+                socket: Socket::Tcp(s),
+                peer_address: Some(addr),
+                client_certificate_names: vec![],
+            }),
+            RawListener::Unix(l) => l.accept().await.map(|(s, _)| Stream {
+                socket: Socket::Unix(s),
+                peer_address: None,
+                client_certificate_names: vec![],
+            }),
+        }
+    }
+
+    fn from_fd(fd: OwnedFd) -> Result<Self> {
+        let listener = std::net::TcpListener::from(fd);
+        if listener.local_addr().is_ok() {
+            listener.set_nonblocking(true)?;
+            return Ok(RawListener::Tcp(TcpListener::from_std(listener)?));
+        }
+        let listener = std::os::unix::net::UnixListener::from(OwnedFd::from(listener));
+        listener.local_addr().context("inherited socket is neither tcp nor unix")?;
+        listener.set_nonblocking(true)?;
+        Ok(RawListener::Unix(UnixListener::from_std(listener)?))
+    }
+}
+
+pub struct ListenOptions {
+    pub address: String,
+    pub unix_socket_mode: Option<u32>,
+    pub unix_socket_owner: Option<String>,
+    pub proxy_protocol: bool,
+    pub socket_activation: Option<systemd::ListenEnv>,
+    pub tls: Option<Arc<rustls::ServerConfig>>,
+    pub client_certificate_field: tls::CertificateField,
+}
+
+pub struct Listener {
+    receiver: mpsc::Receiver<Connection>,
+}
+
+impl Listener {
+    pub async fn bind(options: ListenOptions) -> Result<Self> {
+        let inherited = match options.socket_activation {
+            Some(listen) => listen.into_listen_fd()?,
+            None => None,
+        };
+        let listener = match inherited {
+            Some(fd) => {
+                log::info!("using socket passed by systemd instead of '{}'", options.address);
+                RawListener::from_fd(fd).context("could not use socket passed by systemd")?
+            }
+            None => match options.address.strip_prefix(UNIX_ADDRESS_PREFIX) {
+                Some(path) => {
+                    let path = PathBuf::from(path);
+                    let owner = options.unix_socket_owner.as_deref();
+                    RawListener::Unix(unix::bind(&path, options.unix_socket_mode, owner)?)
+                }
+                None => {
+                    let address: SocketAddr =
+                        options.address.parse().context("could not parse address")?;
+                    RawListener::Tcp(
+                        TcpListener::bind(address).await.context("could not bind address")?,
+                    )
+                }
+            },
+        };
+        let preparer = Preparer {
+            proxy_protocol: options.proxy_protocol,
+            tls: options.tls.map(TlsAcceptor::from),
+            client_certificate_field: options.client_certificate_field,
+        };
+        let (sender, receiver) = mpsc::channel(ACCEPT_QUEUE_SIZE);
+        tokio::spawn(accept_loop(listener, preparer, sender));
+        Ok(Self { receiver })
+    }
+}
+
+impl Accept for Listener {
+    type Conn = Connection;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.get_mut().receiver.poll_recv(cx).map(|conn| conn.map(Ok))
+    }
+}
+
+struct Preparer {
+    proxy_protocol: bool,
+    tls: Option<TlsAcceptor>,
+    client_certificate_field: tls::CertificateField,
+}
+
+impl Preparer {
+    fn is_noop(&self) -> bool {
+        !self.proxy_protocol && self.tls.is_none()
+    }
+
+    async fn prepare(&self, mut stream: Stream) -> Result<Connection> {
+        if self.proxy_protocol {
+            let header = proxy_protocol::read_header(&mut stream);
+            let address = tokio::time::timeout(PROXY_PROTOCOL_TIMEOUT, header)
+                .await
+                .context("timed out waiting for proxy protocol header")??;
+            if let Some(address) = address {
+                stream.peer_address = Some(address);
+            }
+        }
+        match &self.tls {
+            Some(acceptor) => {
+                let mut stream =
+                    tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+                        .await
+                        .context("timed out waiting for tls handshake")?
+                        .context("tls handshake failed")?;
+                let (inner, session) = stream.get_mut();
+                if let Some(cert) = session.peer_certificates().and_then(|certs| certs.first()) {
+                    inner.client_certificate_names =
+                        tls::certificate_names(&cert.0, self.client_certificate_field);
+                }
+                Ok(Connection::Tls(Box::new(stream)))
+            }
+            None => Ok(Connection::Plain(stream)),
+        }
+    }
+}
+
+// Connections are prepared in their own tasks so that a slow client cannot hold up the others,
+// but only up to a limit, after which accepting waits for a pending handshake to finish.
+// Accepting stops, and the socket is closed, once the server drops the listener at shutdown.
+async fn accept_loop(listener: RawListener, preparer: Preparer, sender: mpsc::Sender<Connection>) {
+    let preparer = Arc::new(preparer);
+    let pending = Arc::new(Semaphore::new(MAX_PENDING_HANDSHAKES));
+    let mut backoff = ACCEPT_ERROR_MIN_BACKOFF;
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = sender.closed() => return,
+        };
+        let stream = match accepted {
+            Ok(stream) => {
+                backoff = ACCEPT_ERROR_MIN_BACKOFF;
+                stream
+            }
+            Err(err) => {
+                // Errors such as running out of file descriptors persist for a while.
+                log::warn!("could not accept connection: {}", err);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(ACCEPT_ERROR_MAX_BACKOFF);
+                continue;
+            }
+        };
+        if preparer.is_noop() {
+            if sender.send(Connection::Plain(stream)).await.is_err() {
+                return;
+            }
+            continue;
+        }
+        let permit = tokio::select! {
+            permit = pending.clone().acquire_owned() => permit.expect("semaphore closed"),
+            _ = sender.closed() => return,
+        };
+        let preparer = preparer.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            let peer = stream.peer_address;
+            let result = preparer.prepare(stream).await;
+            drop(permit);
+            match result {
+                Ok(conn) => {
+                    let _ = sender.send(conn).await;
+                }
+                Err(err) => log::debug!("dropping connection from {:?}: {:#}", peer, err),
+            }
+        });
+    }
+}
+
+pub async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("could not install signal handler");
+    let mut interrupt = signal(SignalKind::interrupt()).expect("could not install signal handler");
+    tokio::select! {
+        _ = terminate.recv() => log::info!("received SIGTERM"),
+        _ = interrupt.recv() => log::info!("received SIGINT"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_accept_loop_stops_with_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let preparer = Preparer {
+            proxy_protocol: false,
+            tls: None,
+            client_certificate_field: Default::default(),
+        };
+        let (sender, receiver) = mpsc::channel(ACCEPT_QUEUE_SIZE);
+        let accepting = tokio::spawn(accept_loop(RawListener::Tcp(listener), preparer, sender));
+
+        let mut listener = Listener { receiver };
+        let _client = TcpStream::connect(address).await.unwrap();
+        assert!(listener.receiver.recv().await.is_some());
+        drop(listener);
+        tokio::time::timeout(Duration::from_secs(5), accepting).await.unwrap().unwrap();
+        assert!(TcpStream::connect(address).await.is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use hyper::client::conn;
+use hyper::{Body, Request, StatusCode};
+use rustls::{ClientConfig, ServerName};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+use tokio_rustls::TlsConnector;
+
+use super::UNIX_ADDRESS_PREFIX;
+
+// A proxy protocol header that keeps the probe's own address.
+const PROXY_HEADER: &[u8] = b"PROXY UNKNOWN\r\n";
+
+// How to talk to the listener, which has to match how it is configured.
+#[derive(Clone, Default)]
+pub struct ProbeOptions {
+    pub proxy_protocol: bool,
+    pub tls: Option<Arc<ClientConfig>>,
+}
+
+async fn send_request<S>(stream: S, path: &str) -> Result<StatusCode>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = conn::handshake(stream).await.context("handshake failed")?;
+    tokio::spawn(connection);
+    let req = Request::get(path).header("Host", "localhost").body(Body::empty())?;
+    let resp = sender.send_request(req).await.context("request failed")?;
+    Ok(resp.status())
+}
+
+async fn request<S>(mut stream: S, path: &str, options: &ProbeOptions) -> Result<StatusCode>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    if options.proxy_protocol {
+        stream.write_all(PROXY_HEADER).await.context("could not send proxy protocol header")?;
+    }
+    match &options.tls {
+        Some(config) => {
+            let name = ServerName::try_from("localhost").unwrap();
+            let stream = TlsConnector::from(config.clone())
+                .connect(name, stream)
+                .await
+                .context("tls handshake failed")?;
+            send_request(stream, path).await
+        }
+        None => send_request(stream, path).await,
+    }
+}
+
+async fn probe_inner(address: &str, path: &str, options: &ProbeOptions) -> Result<StatusCode> {
+    match address.strip_prefix(UNIX_ADDRESS_PREFIX) {
+        Some(path_name) => {
+            let stream = UnixStream::connect(path_name).await.context("could not connect")?;
+            request(stream, path, options).await
+        }
+        None => {
+            let address: SocketAddr = address.parse().context("could not parse address")?;
+            let stream = TcpStream::connect(address).await.context("could not connect")?;
+            request(stream, path, options).await
+        }
+    }
+}
+
+pub async fn probe(
+    address: &str,
+    path: &str,
+    timeout: Duration,
+    options: &ProbeOptions,
+) -> Result<StatusCode> {
+    tokio::time::timeout(timeout, probe_inner(address, path, options))
+        .await
+        .context("timed out waiting for response")?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rcgen::generate_simple_self_signed;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    use crate::server::{tls, Preparer, Socket, Stream};
+
+    const OK: &[u8] = b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n";
+
+    // Serves one request the way the listener prepares connections.
+    async fn serve_prepared(preparer: Preparer) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, peer_address) = listener.accept().await.unwrap();
+            let socket = Socket::Tcp(socket);
+            let stream = Stream {
+                socket,
+                peer_address: Some(peer_address),
+                client_certificate_names: vec![],
+            };
+            let Ok(mut conn) = preparer.prepare(stream).await else {
+                return;
+            };
+            let mut buf = [0u8; 1024];
+            let _ = conn.read(&mut buf).await.unwrap();
+            conn.write_all(OK).await.unwrap();
+            conn.flush().await.unwrap();
+        });
+        address
+    }
+
+    async fn serve_once(response: &'static [u8]) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf).await.unwrap();
+            stream.write_all(response).await.unwrap();
+        });
+        address
+    }
+
+    #[tokio::test]
+    async fn test_probe_ok() {
+        let address = serve_once(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n").await;
+        let actual =
+            probe(&address.to_string(), "/readyz", Duration::from_secs(5), &Default::default())
+                .await;
+        assert_eq!(StatusCode::OK, actual.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_probe_unavailable() {
+        let address =
+            serve_once(b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\r\n").await;
+        let actual =
+            probe(&address.to_string(), "/readyz", Duration::from_secs(5), &Default::default())
+                .await;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, actual.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_probe_proxy_protocol() {
+        let preparer = Preparer {
+            proxy_protocol: true,
+            tls: None,
+            client_certificate_field: Default::default(),
+        };
+        let address = serve_prepared(preparer).await;
+        let options = ProbeOptions { proxy_protocol: true, tls: None };
+        let actual = probe(&address.to_string(), "/readyz", Duration::from_secs(5), &options).await;
+        assert_eq!(StatusCode::OK, actual.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_probe_tls() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert_file, key_file) = (dir.path().join("cert.pem"), dir.path().join("key.pem"));
+        let cert = generate_simple_self_signed(vec!["server.example.com".into()]).unwrap();
+        std::fs::write(&cert_file, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_file, cert.serialize_private_key_pem()).unwrap();
+        let other_file = dir.path().join("other.pem");
+        let other = generate_simple_self_signed(vec!["server.example.com".into()]).unwrap();
+        std::fs::write(&other_file, other.serialize_pem().unwrap()).unwrap();
+
+        let server = tls::load_server_config(&cert_file, &key_file, None).await.unwrap();
+        let tls = Some(TlsAcceptor::from(server));
+        let preparer =
+            Preparer { proxy_protocol: false, tls, client_certificate_field: Default::default() };
+        let address = serve_prepared(preparer).await;
+        let tls = Some(tls::load_pinned_client_config(&cert_file).await.unwrap());
+        let options = ProbeOptions { proxy_protocol: false, tls };
+        let actual = probe(&address.to_string(), "/readyz", Duration::from_secs(5), &options).await;
+        assert_eq!(StatusCode::OK, actual.unwrap());
+
+        let server = tls::load_server_config(&cert_file, &key_file, None).await.unwrap();
+        let tls = Some(TlsAcceptor::from(server));
+        let preparer =
+            Preparer { proxy_protocol: false, tls, client_certificate_field: Default::default() };
+        let address = serve_prepared(preparer).await;
+        let tls = Some(tls::load_pinned_client_config(&other_file).await.unwrap());
+        let options = ProbeOptions { proxy_protocol: false, tls };
+        let actual = probe(&address.to_string(), "/readyz", Duration::from_secs(5), &options).await;
+        assert!(actual.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_probe_connection_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        let actual =
+            probe(&address.to_string(), "/readyz", Duration::from_secs(5), &Default::default())
+                .await;
+        assert!(actual.is_err());
+    }
+}
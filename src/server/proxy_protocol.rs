@@ -0,0 +1,185 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER_LENGTH: usize = 16;
+
+#[derive(Debug, Error)]
+pub enum ProxyProtocolError {
+    #[error("could not read proxy protocol header: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("missing proxy protocol header")]
+    MissingHeader,
+    #[error("malformed proxy protocol header")]
+    Malformed,
+}
+
+fn parse_v1(line: &str) -> Result<Option<SocketAddr>, ProxyProtocolError> {
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", proto @ ("TCP4" | "TCP6"), src, _dst, src_port, _dst_port] => {
+            let ip: IpAddr = src.parse().map_err(|_| ProxyProtocolError::Malformed)?;
+            if ip.is_ipv4() != (*proto == "TCP4") {
+                return Err(ProxyProtocolError::Malformed);
+            }
+            let port: u16 = src_port.parse().map_err(|_| ProxyProtocolError::Malformed)?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(ProxyProtocolError::Malformed),
+    }
+}
+
+fn parse_v2(header: &[u8], body: &[u8]) -> Result<Option<SocketAddr>, ProxyProtocolError> {
+    let version_command = header[12];
+    if version_command >> 4 != 2 {
+        return Err(ProxyProtocolError::Malformed);
+    }
+    match version_command & 0x0f {
+        0x0 => return Ok(None),
+        0x1 => {}
+        _ => return Err(ProxyProtocolError::Malformed),
+    }
+    match header[13] >> 4 {
+        0x1 if body.len() >= 12 => {
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let port = u16::from_be_bytes([body[8], body[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        0x2 if body.len() >= 36 => {
+            let octets: [u8; 16] = body[0..16].try_into().unwrap();
+            let port = u16::from_be_bytes([body[32], body[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port)))
+        }
+        0x0 | 0x3 => Ok(None),
+        _ => Err(ProxyProtocolError::Malformed),
+    }
+}
+
+pub async fn read_header<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<Option<SocketAddr>, ProxyProtocolError> {
+    let mut prefix = [0u8; V1_PREFIX.len()];
+    reader.read_exact(&mut prefix).await?;
+
+    if prefix == V1_PREFIX {
+        let mut line = prefix.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LENGTH {
+                return Err(ProxyProtocolError::Malformed);
+            }
+            line.push(reader.read_u8().await?);
+        }
+        let line = std::str::from_utf8(&line[..line.len() - 2])
+            .map_err(|_| ProxyProtocolError::Malformed)?;
+        return parse_v1(line);
+    }
+
+    if prefix != V2_SIGNATURE[..prefix.len()] {
+        return Err(ProxyProtocolError::MissingHeader);
+    }
+    let mut header = [0u8; V2_HEADER_LENGTH];
+    header[..prefix.len()].copy_from_slice(&prefix);
+    reader.read_exact(&mut header[prefix.len()..]).await?;
+    if header[..V2_SIGNATURE.len()] != V2_SIGNATURE[..] {
+        return Err(ProxyProtocolError::MissingHeader);
+    }
+    let length = u16::from_be_bytes([header[14], header[15]]) as usize;
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).await?;
+    parse_v2(&header, &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(mut input: &[u8]) -> (Result<Option<SocketAddr>, ProxyProtocolError>, Vec<u8>) {
+        let result = read_header(&mut input).await;
+        (result, input.to_vec())
+    }
+
+    fn v2_header(command: u8, family: u8, body: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        header.extend_from_slice(body);
+        header
+    }
+
+    #[tokio::test]
+    async fn test_read_header_v1_tcp4() {
+        let input = b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\nGET / HTTP/1.1\r\n";
+        let (actual, rest) = read(input).await;
+        assert_eq!(Some("192.0.2.1:56324".parse().unwrap()), actual.unwrap());
+        assert_eq!(b"GET / HTTP/1.1\r\n".to_vec(), rest);
+    }
+
+    #[tokio::test]
+    async fn test_read_header_v1_tcp6() {
+        let input = b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n";
+        let (actual, _) = read(input).await;
+        assert_eq!(Some("[2001:db8::1]:56324".parse().unwrap()), actual.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_read_header_v1_unknown() {
+        let input = b"PROXY UNKNOWN\r\n";
+        let (actual, _) = read(input).await;
+        assert_eq!(None, actual.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_read_header_v1_mismatched_family() {
+        let input = b"PROXY TCP6 192.0.2.1 192.0.2.2 56324 443\r\n";
+        let (actual, _) = read(input).await;
+        assert!(matches!(actual, Err(ProxyProtocolError::Malformed)));
+    }
+
+    #[tokio::test]
+    async fn test_read_header_v1_too_long() {
+        let input = [b"PROXY ".as_slice(), &[b'A'; 200]].concat();
+        let (actual, _) = read(&input).await;
+        assert!(matches!(actual, Err(ProxyProtocolError::Malformed)));
+    }
+
+    #[tokio::test]
+    async fn test_read_header_v2_ipv4() {
+        let body = [192, 0, 2, 1, 192, 0, 2, 2, 0xdc, 0x04, 0x01, 0xbb];
+        let input = [v2_header(0x1, 0x11, &body), b"GET".to_vec()].concat();
+        let (actual, rest) = read(&input).await;
+        assert_eq!(Some("192.0.2.1:56324".parse().unwrap()), actual.unwrap());
+        assert_eq!(b"GET".to_vec(), rest);
+    }
+
+    #[tokio::test]
+    async fn test_read_header_v2_ipv6_with_tlvs() {
+        let mut body = vec![0u8; 36];
+        body[0..16].copy_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        body[32..34].copy_from_slice(&56324u16.to_be_bytes());
+        body.extend_from_slice(&[0x04, 0x00, 0x01, 0x00]);
+        let input = v2_header(0x1, 0x21, &body);
+        let (actual, rest) = read(&input).await;
+        assert_eq!(Some("[2001:db8::1]:56324".parse().unwrap()), actual.unwrap());
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_header_v2_local() {
+        let input = v2_header(0x0, 0x00, &[]);
+        let (actual, _) = read(&input).await;
+        assert_eq!(None, actual.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_read_header_missing() {
+        let input = b"GET / HTTP/1.1\r\n";
+        let (actual, _) = read(input).await;
+        assert!(matches!(actual, Err(ProxyProtocolError::MissingHeader)));
+    }
+}
@@ -0,0 +1,150 @@
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+const LISTEN_FDS_START: i32 = 3;
+
+fn is_own_pid(pid: Option<String>) -> bool {
+    pid.and_then(|p| p.parse::<u32>().ok()) == Some(std::process::id())
+}
+
+// The socket activation variables, which are read before the runtime starts because changing
+// the environment is not safe once other threads are running.
+#[derive(Debug, Default)]
+pub struct ListenEnv {
+    pid: Option<String>,
+    fds: Option<String>,
+}
+
+impl ListenEnv {
+    // The variables are unset so that they are not inherited by child processes. Must be called
+    // before any other thread is started.
+    pub fn take() -> Self {
+        let listen = Self { pid: env::var("LISTEN_PID").ok(), fds: env::var("LISTEN_FDS").ok() };
+        for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            env::remove_var(name);
+        }
+        listen
+    }
+
+    // Consumes the variables so that the socket cannot be taken twice.
+    pub fn into_listen_fd(self) -> Result<Option<OwnedFd>> {
+        let own = is_own_pid(self.pid);
+        match parse_listen_fds(own, self.fds.as_deref())? {
+            0 => Ok(None),
+            1 => Ok(Some(unsafe { OwnedFd::from_raw_fd(LISTEN_FDS_START) })),
+            n => bail!("expected one socket from systemd but got {}", n),
+        }
+    }
+}
+
+fn parse_listen_fds(own_pid: bool, fds: Option<&str>) -> Result<u32> {
+    match fds {
+        Some(fds) if own_pid => fds.parse().context("invalid LISTEN_FDS"),
+        _ => Ok(0),
+    }
+}
+
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if !is_own_pid(Some(pid)) {
+            return None;
+        }
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+fn send_notification(path: &OsStr, state: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    match path.as_encoded_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        _ => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn notify(state: &str) {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(err) = send_notification(&path, state) {
+        log::warn!("could not notify systemd of '{}': {}", state, err);
+    }
+}
+
+pub fn spawn_watchdog() {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    log::debug!("sending watchdog notifications every {:?}", interval);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            notify("WATCHDOG=1");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_notification_ok() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        send_notification(path.as_os_str(), "READY=1").unwrap();
+        let mut buf = [0u8; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(b"READY=1", &buf[..len]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_send_notification_abstract() {
+        use std::os::linux::net::SocketAddrExt;
+        let name = format!("staticauth-test-{}", std::process::id());
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(&name).unwrap();
+        let receiver = UnixDatagram::bind_addr(&addr).unwrap();
+        send_notification(OsStr::new(&format!("@{}", name)), "STOPPING=1").unwrap();
+        let mut buf = [0u8; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(b"STOPPING=1", &buf[..len]);
+    }
+
+    #[test]
+    fn test_parse_listen_fds() {
+        assert_eq!(1, parse_listen_fds(true, Some("1")).unwrap());
+        assert_eq!(0, parse_listen_fds(false, Some("1")).unwrap());
+        assert_eq!(0, parse_listen_fds(true, None).unwrap());
+        assert!(parse_listen_fds(true, Some("one")).is_err());
+    }
+
+    #[test]
+    fn test_listen_fd_other_pid() {
+        let listen = ListenEnv { pid: Some("1".into()), fds: Some("1".into()) };
+        assert!(listen.into_listen_fd().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_send_notification_missing_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing.sock");
+        assert!(send_notification(path.as_os_str(), "READY=1").is_err());
+    }
+}
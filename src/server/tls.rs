@@ -0,0 +1,183 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Context, Result};
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::server::{AllowAnyAnonymousOrAuthenticatedClient, NoClientAuth};
+use rustls::{
+    Certificate, CertificateError, ClientConfig, PrivateKey, RootCertStore, ServerConfig,
+    ServerName,
+};
+use rustls_pemfile::Item;
+use serde::Deserialize;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+async fn read_certificates(path: &Path) -> Result<Vec<Certificate>> {
+    let content = tokio::fs::read(path).await.context("could not read certificate file")?;
+    let certs = rustls_pemfile::certs(&mut content.as_slice())
+        .context("could not parse certificate file")?;
+    if certs.is_empty() {
+        return Err(anyhow!("no certificate found in {}", path.display()));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+async fn read_private_key(path: &Path) -> Result<PrivateKey> {
+    let content = tokio::fs::read(path).await.context("could not read private key file")?;
+    let items = rustls_pemfile::read_all(&mut content.as_slice())
+        .context("could not parse private key file")?;
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("no private key found in {}", path.display()))
+}
+
+pub async fn load_server_config(
+    certificate_file: &Path,
+    private_key_file: &Path,
+    client_ca_file: Option<&Path>,
+) -> Result<Arc<ServerConfig>> {
+    let certs = read_certificates(certificate_file).await?;
+    let key = read_private_key(private_key_file).await?;
+    let verifier = match client_ca_file {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certificates(path).await? {
+                roots.add(&cert).context("invalid client ca certificate")?;
+            }
+            AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed()
+        }
+        None => NoClientAuth::boxed(),
+    };
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certs, key)
+        .context("invalid server certificate or private key")?;
+    Ok(Arc::new(config))
+}
+
+// Accepts only the given certificate, whatever its names and issuer, for probing the server's
+// own listener.
+struct PinnedCertificate(Certificate);
+
+impl ServerCertVerifier for PinnedCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match *end_entity == self.0 {
+            true => Ok(ServerCertVerified::assertion()),
+            false => Err(rustls::Error::InvalidCertificate(CertificateError::UnknownIssuer)),
+        }
+    }
+}
+
+pub async fn load_pinned_client_config(certificate_file: &Path) -> Result<Arc<ClientConfig>> {
+    let cert = read_certificates(certificate_file).await?.swap_remove(0);
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(PinnedCertificate(cert)))
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+// The certificate field whose values are taken as usernames.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CertificateField {
+    #[default]
+    CommonName,
+    DnsName,
+    Email,
+}
+
+pub fn certificate_names(der: &[u8], field: CertificateField) -> Vec<String> {
+    let Ok((_, cert)) = X509Certificate::from_der(der) else {
+        return vec![];
+    };
+    if field == CertificateField::CommonName {
+        return cert
+            .subject()
+            .iter_common_name()
+            .filter_map(|cn| cn.as_str().ok())
+            .map(|cn| cn.to_owned())
+            .collect();
+    }
+    let Ok(Some(san)) = cert.subject_alternative_name() else {
+        return vec![];
+    };
+    san.value
+        .general_names
+        .iter()
+        .filter_map(|name| match (field, name) {
+            (CertificateField::DnsName, GeneralName::DNSName(n))
+            | (CertificateField::Email, GeneralName::RFC822Name(n)) => Some(n.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rcgen::{CertificateParams, DistinguishedName, DnType, SanType};
+
+    fn generate_certificate(cn: Option<&str>, sans: Vec<SanType>) -> Vec<u8> {
+        let mut params = CertificateParams::default();
+        let mut dn = DistinguishedName::new();
+        if let Some(cn) = cn {
+            dn.push(DnType::CommonName, cn);
+        }
+        params.distinguished_name = dn;
+        params.subject_alt_names = sans;
+        rcgen::Certificate::from_params(params).unwrap().serialize_der().unwrap()
+    }
+
+    #[test]
+    fn test_certificate_names_common_name() {
+        let der = generate_certificate(Some("user"), vec![SanType::DnsName("other".into())]);
+        let expected = vec!["user".to_owned()];
+        let actual = certificate_names(&der, CertificateField::CommonName);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_certificate_names_subject_alt_names() {
+        let sans = vec![
+            SanType::DnsName("host.example.com".into()),
+            SanType::Rfc822Name("user@example.com".into()),
+        ];
+        let der = generate_certificate(Some("user"), sans);
+        let expected = vec!["host.example.com".to_owned()];
+        let actual = certificate_names(&der, CertificateField::DnsName);
+        assert_eq!(expected, actual);
+        let expected = vec!["user@example.com".to_owned()];
+        let actual = certificate_names(&der, CertificateField::Email);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_certificate_names_missing_field() {
+        let der = generate_certificate(None, vec![]);
+        assert!(certificate_names(&der, CertificateField::CommonName).is_empty());
+        assert!(certificate_names(&der, CertificateField::Email).is_empty());
+    }
+
+    #[test]
+    fn test_certificate_names_invalid_der() {
+        let actual = certificate_names(b"invalid", CertificateField::CommonName);
+        assert!(actual.is_empty());
+    }
+}
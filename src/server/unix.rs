@@ -0,0 +1,180 @@
+use std::fs::Permissions;
+use std::io::ErrorKind;
+use std::os::unix::fs::{chown, FileTypeExt, PermissionsExt};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use nix::unistd::{Group, User};
+use tokio::net::UnixListener;
+
+fn resolve_user(name: &str) -> Result<u32> {
+    if let Ok(uid) = name.parse() {
+        return Ok(uid);
+    }
+    let user = User::from_name(name).context("could not look up user")?;
+    user.map(|u| u.uid.as_raw()).ok_or_else(|| anyhow!("unknown user '{}'", name))
+}
+
+fn resolve_group(name: &str) -> Result<u32> {
+    if let Ok(gid) = name.parse() {
+        return Ok(gid);
+    }
+    let group = Group::from_name(name).context("could not look up group")?;
+    group.map(|g| g.gid.as_raw()).ok_or_else(|| anyhow!("unknown group '{}'", name))
+}
+
+pub fn parse_owner(owner: &str) -> Result<(Option<u32>, Option<u32>)> {
+    let (user, group) = match owner.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (owner, None),
+    };
+    let uid = match user {
+        "" => None,
+        user => Some(resolve_user(user)?),
+    };
+    let gid = match group {
+        None | Some("") => None,
+        Some(group) => Some(resolve_group(group)?),
+    };
+    Ok((uid, gid))
+}
+
+pub fn parse_mode(mode: &str) -> Result<u32> {
+    let mode = u32::from_str_radix(mode, 8).context("socket mode must be an octal number")?;
+    if mode > 0o7777 {
+        return Err(anyhow!("socket mode is out of range"));
+    }
+    Ok(mode)
+}
+
+// A socket is only removed if nothing answers on it, so that a second instance does not take
+// over the socket of a running one.
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    let Ok(meta) = std::fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+    if !meta.file_type().is_socket() {
+        return Err(anyhow!("'{}' exists and is not a socket", path.display()));
+    }
+    match UnixStream::connect(path) {
+        Ok(_) => Err(anyhow!("another process is listening on '{}'", path.display())),
+        Err(err) if err.kind() == ErrorKind::ConnectionRefused => {
+            std::fs::remove_file(path).context("could not remove stale socket")
+        }
+        Err(err) => Err(err).context("could not check existing socket"),
+    }
+}
+
+pub fn bind(path: &Path, mode: Option<u32>, owner: Option<&str>) -> Result<UnixListener> {
+    remove_stale_socket(path)?;
+    // The socket is bound inside a private directory and only moved into place once its mode
+    // and owner are set, so that it is never reachable with looser permissions.
+    let parent = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    let dir = tempfile::Builder::new()
+        .prefix(".staticauth")
+        .tempdir_in(parent)
+        .context("could not create socket directory")?;
+    let private_path = dir.path().join("socket");
+    let listener = UnixListener::bind(&private_path).context("could not bind unix socket")?;
+    std::fs::set_permissions(&private_path, Permissions::from_mode(mode.unwrap_or(0o600)))
+        .context("could not set socket mode")?;
+    if let Some(owner) = owner {
+        let (uid, gid) = parse_owner(owner)?;
+        chown(&private_path, uid, gid).context("could not set socket owner")?;
+    }
+    std::fs::rename(&private_path, path).context("could not move unix socket into place")?;
+    Ok(listener)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mode_ok() {
+        let expected = 0o660;
+        let actual = parse_mode("0660").unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_parse_mode_invalid() {
+        assert!(parse_mode("0999").is_err());
+        assert!(parse_mode("77777").is_err());
+    }
+
+    #[test]
+    fn test_parse_owner_numeric() {
+        let expected = (Some(1000), Some(100));
+        let actual = parse_owner("1000:100").unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_parse_owner_group_only() {
+        let expected = (None, Some(100));
+        let actual = parse_owner(":100").unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[tokio::test]
+    async fn test_bind_sets_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("staticauth.sock");
+        let _listener = bind(&path, Some(0o600), None).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o7777);
+    }
+
+    #[tokio::test]
+    async fn test_bind_removes_private_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("staticauth.sock");
+        let listener = bind(&path, None, None).unwrap();
+        let expected = vec![path.clone()];
+        let actual: Vec<_> =
+            std::fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(expected, actual);
+        let client = tokio::net::UnixStream::connect(&path).await;
+        assert!(client.is_ok());
+        assert!(listener.accept().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_bind_replaces_stale_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("staticauth.sock");
+        drop(bind(&path, None, None).unwrap());
+        assert!(bind(&path, None, None).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_bind_without_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("staticauth.sock");
+        let _listener = bind(&path, None, None).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o7777);
+    }
+
+    #[tokio::test]
+    async fn test_bind_refuses_live_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("staticauth.sock");
+        let _listener = bind(&path, None, None).unwrap();
+        assert!(bind(&path, None, None).is_err());
+        assert!(UnixStream::connect(&path).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_bind_refuses_regular_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("staticauth.sock");
+        std::fs::write(&path, "").unwrap();
+        assert!(bind(&path, None, None).is_err());
+    }
+}
@@ -1,9 +1,26 @@
+pub mod access_log;
+pub mod audit;
 pub mod auth;
+pub mod client;
+pub mod connection;
+pub mod handoff;
+pub mod hashing;
 pub mod headers;
+pub mod health;
+pub mod lockout;
+pub mod metrics;
+pub mod origin;
 pub mod page;
+pub mod password_policy;
+pub mod rate_limit;
+pub mod realm;
 pub mod redirection;
 pub mod router;
 pub mod session;
+pub mod telemetry;
+pub mod user_store;
 
 pub use auth::hash_password;
-pub use router::ServiceConfig;
+pub use client::TrustedProxies;
+pub use connection::ConnectionInfo;
+pub use router::{ServiceConfig, ServiceState};
@@ -0,0 +1,153 @@
+use std::future::Future;
+use std::time::Instant;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::extract::OriginalUri;
+use axum::http::{HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+
+use super::client::ClientAddress;
+use super::headers::X_REQUEST_ID;
+use crate::logging::with_fields;
+
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+// Task locals are not inherited by spawned tasks, so work spawned for a request takes the
+// request id along with these.
+pub fn with_request_id<F: Future>(f: F) -> impl Future<Output = F::Output> {
+    let id = current_request_id();
+    async move {
+        match id {
+            Some(id) => REQUEST_ID.scope(id, f).await,
+            None => f.await,
+        }
+    }
+}
+
+pub fn with_request_id_blocking<T>(f: impl FnOnce() -> T) -> impl FnOnce() -> T {
+    let id = current_request_id();
+    move || match id {
+        Some(id) => REQUEST_ID.sync_scope(id, f),
+        None => f(),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestUser(pub String);
+
+#[derive(Debug, Clone, Copy)]
+pub struct RequestError(pub &'static str);
+
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+fn generate_request_id() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub fn request_id<B>(req: &Request<B>) -> String {
+    let id = req.headers().get(X_REQUEST_ID).and_then(|value| value.to_str().ok());
+    match id {
+        Some(id) if valid_request_id(id) => id.into(),
+        _ => generate_request_id(),
+    }
+}
+
+pub async fn access_log<B>(
+    ClientAddress(client): ClientAddress,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let start = Instant::now();
+    let id = request_id(&req);
+    let header = HeaderValue::from_str(&id).unwrap();
+    req.headers_mut().insert(X_REQUEST_ID, header.clone());
+    let method = req.method().clone();
+    let path = match req.extensions().get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri.path().to_owned(),
+        None => req.uri().path().to_owned(),
+    };
+
+    let mut resp = REQUEST_ID.scope(id.clone(), next.run(req)).await;
+    resp.headers_mut().insert(X_REQUEST_ID, header);
+
+    let latency = start.elapsed().as_secs_f64() * 1000.0;
+    let mut fields = vec![
+        ("request_id", id),
+        ("method", method.to_string()),
+        ("path", path),
+        ("status", resp.status().as_u16().to_string()),
+        ("latency_ms", format!("{:.3}", latency)),
+    ];
+    if let Some(RequestUser(user)) = resp.extensions().get() {
+        fields.push(("user", user.clone()));
+    }
+    if let Some(client) = client {
+        fields.push(("client_ip", client.to_string()));
+    }
+    if let Some(RequestError(kind)) = resp.extensions().get() {
+        fields.push(("error", kind.to_string()));
+    }
+    with_fields(fields, || log::info!(target: "staticauth::access", "request completed"));
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+
+    use super::*;
+
+    #[test]
+    fn test_request_id_propagated() {
+        let req = Request::get("/").header(X_REQUEST_ID, "abc-123").body(Body::empty()).unwrap();
+        assert_eq!("abc-123", request_id(&req));
+    }
+
+    #[test]
+    fn test_request_id_generated() {
+        let req = Request::get("/").body(Body::empty()).unwrap();
+        assert_eq!(32, request_id(&req).len());
+
+        let req = Request::get("/").header(X_REQUEST_ID, "a b").body(Body::empty()).unwrap();
+        assert_eq!(32, request_id(&req).len());
+
+        let long = "a".repeat(MAX_REQUEST_ID_LEN + 1);
+        let req = Request::get("/").header(X_REQUEST_ID, long).body(Body::empty()).unwrap();
+        assert_eq!(32, request_id(&req).len());
+    }
+
+    #[tokio::test]
+    async fn test_current_request_id() {
+        assert_eq!(None, current_request_id());
+        let actual = REQUEST_ID.scope("abc".into(), async { current_request_id() }).await;
+        assert_eq!(Some("abc".into()), actual);
+    }
+
+    #[tokio::test]
+    async fn test_with_request_id() {
+        let spawned = REQUEST_ID.scope("abc".into(), async {
+            let task = tokio::spawn(with_request_id(async { current_request_id() }));
+            let blocking =
+                tokio::task::spawn_blocking(with_request_id_blocking(current_request_id));
+            (task.await.unwrap(), blocking.await.unwrap())
+        });
+        assert_eq!((Some("abc".into()), Some("abc".into())), spawned.await);
+        assert_eq!(
+            None,
+            tokio::spawn(with_request_id(async { current_request_id() })).await.unwrap()
+        );
+    }
+}
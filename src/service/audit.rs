@@ -0,0 +1,291 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Serializer};
+
+pub mod journald;
+pub mod syslog;
+
+const FILE_QUEUE_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditEventKind {
+    LoginSuccess,
+    LoginFailure,
+    Signout,
+    SessionExpired,
+    SessionRevoked,
+    AccountLocked,
+    AccountUnlocked,
+}
+
+impl Serialize for AuditEventKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+// Fields are serialized in declaration order; keep it stable for log parsers such as fail2ban.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEvent<'a> {
+    pub ts: DateTime<Utc>,
+    pub event: AuditEventKind,
+    pub realm: &'a str,
+    pub user: Option<&'a str>,
+    pub client_ip: Option<IpAddr>,
+    pub user_agent: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'a str>,
+}
+
+impl AuditEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEventKind::LoginSuccess => "login_success",
+            AuditEventKind::LoginFailure => "login_failure",
+            AuditEventKind::Signout => "signout",
+            AuditEventKind::SessionExpired => "session_expired",
+            AuditEventKind::SessionRevoked => "session_revoked",
+            AuditEventKind::AccountLocked => "account_locked",
+            AuditEventKind::AccountUnlocked => "account_unlocked",
+        }
+    }
+
+    pub fn outcome(&self) -> &'static str {
+        match self {
+            AuditEventKind::LoginSuccess
+            | AuditEventKind::Signout
+            | AuditEventKind::AccountUnlocked => "success",
+            AuditEventKind::LoginFailure
+            | AuditEventKind::SessionExpired
+            | AuditEventKind::SessionRevoked
+            | AuditEventKind::AccountLocked => "failure",
+        }
+    }
+}
+
+impl<'a> AuditEvent<'a> {
+    pub fn new(event: AuditEventKind, realm: &'a str) -> Self {
+        Self {
+            ts: Utc::now(),
+            event,
+            realm,
+            user: None,
+            client_ip: None,
+            user_agent: None,
+            reason: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path, max_size, max_files, file, size })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for index in (1..self.max_files).rev() {
+                let from = rotated_path(&self.path, index);
+                if from.exists() {
+                    fs::rename(from, rotated_path(&self.path, index + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+            self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(format!("{}\n", line).as_bytes())?;
+        self.file.flush()?;
+        self.size += len;
+        Ok(())
+    }
+}
+
+pub trait AuditSink: std::fmt::Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+    fn send(&self, event: &AuditEvent, line: &str) -> io::Result<()>;
+}
+
+// Lines are written by a dedicated thread, so that recording an event never blocks a request on
+// the disk. Events are reported as lost if the writer falls too far behind.
+#[derive(Debug)]
+pub struct FileSink {
+    sender: Option<SyncSender<String>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl FileSink {
+    pub fn open(path: PathBuf, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let mut file = RotatingFile::open(path, max_size, max_files)?;
+        let (sender, receiver) = sync_channel::<String>(FILE_QUEUE_SIZE);
+        let writer = thread::Builder::new().name("audit-log".into()).spawn(move || {
+            for line in receiver {
+                if let Err(err) = file.write_line(&line) {
+                    log::error!("could not write audit event to file: {}", err);
+                }
+            }
+        })?;
+        Ok(Self { sender: Some(sender), writer: Some(writer) })
+    }
+}
+
+// Waits for the queued lines to be written.
+impl Drop for FileSink {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl AuditSink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn send(&self, _event: &AuditEvent, line: &str) -> io::Result<()> {
+        let sender = self.sender.as_ref().expect("file sink is closed");
+        match sender.try_send(line.to_owned()) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                Err(io::Error::new(ErrorKind::WouldBlock, "audit log queue is full"))
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                Err(io::Error::new(ErrorKind::BrokenPipe, "audit log writer has stopped"))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    sinks: Vec<Box<dyn AuditSink>>,
+}
+
+impl AuditLog {
+    pub fn new(sinks: Vec<Box<dyn AuditSink>>) -> Self {
+        Self { sinks }
+    }
+
+    pub fn record(&self, event: &AuditEvent) {
+        let line = match serde_json::to_string(event) {
+            Ok(line) => line,
+            Err(err) => {
+                log::error!("could not serialize audit event: {}", err);
+                return;
+            }
+        };
+        log::info!(target: "staticauth::audit", "{}", line);
+        for sink in &self.sinks {
+            if let Err(err) = sink.send(event, &line) {
+                log::error!("could not write audit event to {}: {}", sink.name(), err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(user: &str) -> AuditEvent<'_> {
+        AuditEvent {
+            ts: DateTime::from_timestamp(100000, 0).unwrap(),
+            user: Some(user),
+            client_ip: Some("192.0.2.1".parse().unwrap()),
+            user_agent: Some("curl/8.0"),
+            reason: Some("invalid_credential"),
+            ..AuditEvent::new(AuditEventKind::LoginFailure, "default")
+        }
+    }
+
+    #[test]
+    fn test_audit_event_format() {
+        let expected = concat!(
+            r#"{"ts":"1970-01-02T03:46:40Z","event":"login_failure","realm":"default","#,
+            r#""user":"alice","client_ip":"192.0.2.1","user_agent":"curl/8.0","#,
+            r#""reason":"invalid_credential"}"#,
+        );
+        let actual = serde_json::to_string(&event("alice")).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_audit_event_format_without_reason() {
+        let event = AuditEvent::new(AuditEventKind::Signout, "default");
+        let actual = serde_json::to_string(&event).unwrap();
+        assert!(actual.contains(r#""event":"signout","realm":"default","user":null"#));
+        assert!(!actual.contains("reason"));
+    }
+
+    fn file_log(path: PathBuf, max_size: u64, max_files: usize) -> AuditLog {
+        AuditLog::new(vec![Box::new(FileSink::open(path, max_size, max_files).unwrap())])
+    }
+
+    #[test]
+    fn test_audit_log_writes_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let log = file_log(path.clone(), 1 << 20, 3);
+        log.record(&event("alice"));
+        log.record(&event("bob"));
+        drop(log);
+        let content = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(2, lines.len());
+        assert!(lines[1].contains(r#""user":"bob""#));
+    }
+
+    #[test]
+    fn test_audit_log_rotates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let line_len = serde_json::to_string(&event("u0")).unwrap().len() as u64 + 1;
+        let log = file_log(path.clone(), line_len * 2, 2);
+        for i in 0..7 {
+            log.record(&event(&format!("u{}", i)));
+        }
+        drop(log);
+        let current = fs::read_to_string(&path).unwrap();
+        let first = fs::read_to_string(rotated_path(&path, 1)).unwrap();
+        let second = fs::read_to_string(rotated_path(&path, 2)).unwrap();
+        assert!(current.contains("u6"));
+        assert!(first.contains("u4") && first.contains("u5"));
+        assert!(second.contains("u2") && second.contains("u3"));
+        assert!(!rotated_path(&path, 3).exists());
+    }
+}
@@ -0,0 +1,123 @@
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+
+use super::{AuditEvent, AuditSink};
+
+pub const DEFAULT_SOCKET: &str = "/run/systemd/journal/socket";
+
+const SYSLOG_IDENTIFIER: &str = "staticauth";
+const FACILITY_AUTHPRIV: &str = "10";
+const PRIORITY_NOTICE: &str = "5";
+const PRIORITY_INFO: &str = "6";
+
+fn push_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.extend_from_slice(name.as_bytes());
+    // Values containing newlines use the length-prefixed binary encoding.
+    if value.contains('\n') {
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(b'\n');
+}
+
+pub fn format_entry(event: &AuditEvent, line: &str) -> Vec<u8> {
+    let outcome = event.event.outcome();
+    let priority = if outcome == "success" { PRIORITY_INFO } else { PRIORITY_NOTICE };
+
+    let mut buf = Vec::new();
+    push_field(&mut buf, "MESSAGE", line);
+    push_field(&mut buf, "PRIORITY", priority);
+    push_field(&mut buf, "SYSLOG_FACILITY", FACILITY_AUTHPRIV);
+    push_field(&mut buf, "SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER);
+    push_field(&mut buf, "AUDIT_EVENT", event.event.as_str());
+    push_field(&mut buf, "OUTCOME", outcome);
+    push_field(&mut buf, "REALM", event.realm);
+    if let Some(user) = event.user {
+        push_field(&mut buf, "USERNAME", user);
+    }
+    if let Some(client_ip) = event.client_ip {
+        push_field(&mut buf, "CLIENT_IP", &client_ip.to_string());
+    }
+    if let Some(user_agent) = event.user_agent {
+        push_field(&mut buf, "USER_AGENT", user_agent);
+    }
+    if let Some(reason) = event.reason {
+        push_field(&mut buf, "REASON", reason);
+    }
+    buf
+}
+
+#[derive(Debug)]
+pub struct JournaldSink {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl JournaldSink {
+    pub fn new(path: PathBuf) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket, path })
+    }
+}
+
+impl AuditSink for JournaldSink {
+    fn name(&self) -> &'static str {
+        "journald"
+    }
+
+    fn send(&self, event: &AuditEvent, line: &str) -> io::Result<()> {
+        self.socket.send_to(&format_entry(event, line), &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::audit::AuditEventKind;
+
+    fn event() -> AuditEvent<'static> {
+        AuditEvent {
+            user: Some("alice"),
+            client_ip: Some("192.0.2.1".parse().unwrap()),
+            ..AuditEvent::new(AuditEventKind::LoginSuccess, "default")
+        }
+    }
+
+    #[test]
+    fn test_format_entry() {
+        let expected = concat!(
+            "MESSAGE={}\nPRIORITY=6\nSYSLOG_FACILITY=10\nSYSLOG_IDENTIFIER=staticauth\n",
+            "AUDIT_EVENT=login_success\nOUTCOME=success\nREALM=default\nUSERNAME=alice\n",
+            "CLIENT_IP=192.0.2.1\n",
+        );
+        let actual = format_entry(&event(), "{}");
+        assert_eq!(expected.as_bytes(), actual);
+    }
+
+    #[test]
+    fn test_format_entry_multiline_value() {
+        let event = AuditEvent { user_agent: Some("a\nb"), ..event() };
+        let actual = format_entry(&event, "{}");
+        let expected = b"USER_AGENT\n\x03\0\0\0\0\0\0\0a\nb\n";
+        assert!(actual.windows(expected.len()).any(|w| w == expected));
+    }
+
+    #[test]
+    fn test_send_to_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("socket");
+        let server = UnixDatagram::bind(&path).unwrap();
+        let sink = JournaldSink::new(path).unwrap();
+        sink.send(&event(), "{}").unwrap();
+
+        let mut buf = [0u8; 1024];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(format_entry(&event(), "{}"), &buf[..len]);
+    }
+}
@@ -0,0 +1,133 @@
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+
+use chrono::SecondsFormat;
+
+use super::{AuditEvent, AuditSink};
+
+pub const DEFAULT_SOCKET: &str = "/dev/log";
+
+const APP_NAME: &str = "staticauth";
+const FACILITY_AUTHPRIV: u8 = 10;
+const SEVERITY_NOTICE: u8 = 5;
+const SEVERITY_INFO: u8 = 6;
+// 32473 is the private enterprise number reserved for documentation (RFC 5612).
+const SD_ID: &str = "staticauth@32473";
+
+fn escape_param(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+pub fn format_message(event: &AuditEvent, line: &str, hostname: &str, pid: u32) -> String {
+    let outcome = event.event.outcome();
+    let severity = if outcome == "success" { SEVERITY_INFO } else { SEVERITY_NOTICE };
+    let priority = FACILITY_AUTHPRIV * 8 + severity;
+
+    let mut params = vec![("outcome", outcome.to_string()), ("realm", event.realm.to_string())];
+    if let Some(user) = event.user {
+        params.push(("user", user.to_string()));
+    }
+    if let Some(client_ip) = event.client_ip {
+        params.push(("client_ip", client_ip.to_string()));
+    }
+    if let Some(reason) = event.reason {
+        params.push(("reason", reason.to_string()));
+    }
+    let params: Vec<String> =
+        params.iter().map(|(k, v)| format!("{}=\"{}\"", k, escape_param(v))).collect();
+
+    format!(
+        "<{}>1 {} {} {} {} {} [{} {}] {}",
+        priority,
+        event.ts.to_rfc3339_opts(SecondsFormat::Micros, true),
+        hostname,
+        APP_NAME,
+        pid,
+        event.event.as_str(),
+        SD_ID,
+        params.join(" "),
+        line
+    )
+}
+
+#[derive(Debug)]
+pub struct SyslogSink {
+    socket: UnixDatagram,
+    path: PathBuf,
+    hostname: String,
+}
+
+impl SyslogSink {
+    pub fn new(path: PathBuf) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.set_nonblocking(true)?;
+        let hostname = nix::unistd::gethostname()?.to_string_lossy().into_owned();
+        let hostname = if hostname.is_empty() { "-".into() } else { hostname };
+        Ok(Self { socket, path, hostname })
+    }
+}
+
+impl AuditSink for SyslogSink {
+    fn name(&self) -> &'static str {
+        "syslog"
+    }
+
+    fn send(&self, event: &AuditEvent, line: &str) -> io::Result<()> {
+        let message = format_message(event, line, &self.hostname, std::process::id());
+        self.socket.send_to(message.as_bytes(), &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::service::audit::AuditEventKind;
+
+    fn event() -> AuditEvent<'static> {
+        AuditEvent {
+            ts: DateTime::from_timestamp(100000, 0).unwrap(),
+            user: Some("al\"ice]"),
+            client_ip: Some("192.0.2.1".parse().unwrap()),
+            reason: Some("invalid_credential"),
+            ..AuditEvent::new(AuditEventKind::LoginFailure, "default")
+        }
+    }
+
+    #[test]
+    fn test_format_message() {
+        let expected = concat!(
+            r#"<85>1 1970-01-02T03:46:40.000000Z host staticauth 42 login_failure "#,
+            r#"[staticauth@32473 outcome="failure" realm="default" user="al\"ice\]" "#,
+            r#"client_ip="192.0.2.1" reason="invalid_credential"] {}"#,
+        );
+        let actual = format_message(&event(), "{}", "host", 42);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_send_to_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        let server = UnixDatagram::bind(&path).unwrap();
+        let sink = SyslogSink::new(path).unwrap();
+        sink.send(&event(), "{}").unwrap();
+
+        let mut buf = [0u8; 1024];
+        let len = server.recv(&mut buf).unwrap();
+        let actual = std::str::from_utf8(&buf[..len]).unwrap();
+        assert!(actual.starts_with("<85>1 1970-01-02T03:46:40.000000Z "));
+        assert!(actual.contains(" staticauth "));
+        assert!(actual.contains(r#"client_ip="192.0.2.1""#));
+    }
+}
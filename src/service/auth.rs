@@ -1,51 +1,396 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
 
-use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
 use argon2::password_hash::SaltString;
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::{
+    Algorithm, Argon2, KeyId, Params, ParamsBuilder, PasswordHash, PasswordHasher,
+    PasswordVerifier, Version,
+};
+use base64ct::{Base64, Encoding};
+use pbkdf2::Pbkdf2;
+use pwhash::bcrypt::{BcryptSetup, BcryptVariant};
+use scrypt::Scrypt;
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
 use thiserror::Error;
 
+const BCRYPT_COST: u32 = 12;
+const SHA512_CRYPT_ROUNDS: u32 = 656_000;
+const DJANGO_PBKDF2_MIN_LEN: usize = 16;
+
 #[derive(Debug, PartialEq, Error)]
 pub enum PasswordError {
-    #[error("user list is empty")]
-    EmptyUserList,
     #[error("invalid password hash: {0}")]
     InvalidPasswordHash(argon2::password_hash::Error),
+    #[error("invalid password hash: {0}")]
+    InvalidCryptHash(String),
+    #[error("unsupported password hash algorithm '{0}'")]
+    UnsupportedAlgorithm(String),
     #[error("could not compute password hash: {0}")]
     HashingFailed(argon2::password_hash::Error),
+    #[error("invalid pepper id '{0}'")]
+    InvalidPepperId(String),
+    #[error("unknown pepper id '{0}'")]
+    UnknownPepper(String),
+}
+
+// A server-side secret mixed into argon2 hashes. Its id is recorded as the `keyid` parameter
+// of each hash, so that hashes made with a retired pepper can still be verified.
+#[derive(Clone)]
+pub struct Pepper {
+    id: KeyId,
+    secret: Arc<[u8]>,
+}
+
+impl Pepper {
+    pub fn new(id: &str, secret: Vec<u8>) -> Result<Self, PasswordError> {
+        let invalid = || PasswordError::InvalidPepperId(id.into());
+        if id.is_empty() {
+            return Err(invalid());
+        }
+        let id = KeyId::new(id.as_bytes()).map_err(|_| invalid())?;
+        Ok(Self { id, secret: secret.into() })
+    }
+
+    fn params(&self, params: &Params) -> Result<Params, PasswordError> {
+        let mut builder = ParamsBuilder::new();
+        builder.m_cost(params.m_cost()).t_cost(params.t_cost()).p_cost(params.p_cost());
+        let params = builder.keyid(self.id).build();
+        params.map_err(|err| PasswordError::HashingFailed(err.into()))
+    }
+}
+
+impl fmt::Debug for Pepper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let id = String::from_utf8_lossy(self.id.as_bytes());
+        f.debug_struct("Pepper").field("id", &id).finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Argon2id,
+    Argon2i,
+    Argon2d,
+    Bcrypt,
+    Scrypt,
+    Pbkdf2Sha256,
+    Sha512Crypt,
+}
+
+impl HashAlgorithm {
+    const ALL: [HashAlgorithm; 7] = [
+        HashAlgorithm::Argon2id,
+        HashAlgorithm::Argon2i,
+        HashAlgorithm::Argon2d,
+        HashAlgorithm::Bcrypt,
+        HashAlgorithm::Scrypt,
+        HashAlgorithm::Pbkdf2Sha256,
+        HashAlgorithm::Sha512Crypt,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Argon2id => "argon2id",
+            HashAlgorithm::Argon2i => "argon2i",
+            HashAlgorithm::Argon2d => "argon2d",
+            HashAlgorithm::Bcrypt => "bcrypt",
+            HashAlgorithm::Scrypt => "scrypt",
+            HashAlgorithm::Pbkdf2Sha256 => "pbkdf2-sha256",
+            HashAlgorithm::Sha512Crypt => "sha512-crypt",
+        }
+    }
+
+    pub fn is_argon2(&self) -> bool {
+        matches!(self, HashAlgorithm::Argon2id | HashAlgorithm::Argon2i | HashAlgorithm::Argon2d)
+    }
+
+    // The argon2 parameters and pepper are ignored by the other algorithms, which use fixed
+    // costs and have no secret input.
+    pub fn hash(
+        &self,
+        password: &str,
+        params: &Params,
+        pepper: Option<&Pepper>,
+    ) -> Result<String, PasswordError> {
+        let salt = SaltString::generate(&mut OsRng);
+        let phc = |hasher: &dyn PasswordHasherDyn| {
+            hasher.hash(password.as_bytes(), &salt).map_err(PasswordError::HashingFailed)
+        };
+        let crypt = |result: pwhash::Result<String>| {
+            result.map_err(|err| PasswordError::InvalidCryptHash(err.to_string()))
+        };
+        let argon2 = |variant| match pepper {
+            Some(pepper) => {
+                let params = pepper.params(params)?;
+                Argon2::new_with_secret(&pepper.secret, variant, Version::V0x13, params)
+                    .map_err(|err| PasswordError::HashingFailed(err.into()))
+            }
+            None => Ok(Argon2::new(variant, Version::V0x13, params.clone())),
+        };
+        match self {
+            HashAlgorithm::Argon2id => phc(&argon2(Algorithm::Argon2id)?),
+            HashAlgorithm::Argon2i => phc(&argon2(Algorithm::Argon2i)?),
+            HashAlgorithm::Argon2d => phc(&argon2(Algorithm::Argon2d)?),
+            HashAlgorithm::Bcrypt => {
+                let setup = BcryptSetup {
+                    salt: None,
+                    cost: Some(BCRYPT_COST),
+                    variant: Some(BcryptVariant::V2b),
+                };
+                crypt(pwhash::bcrypt::hash_with(setup, password))
+            }
+            HashAlgorithm::Scrypt => phc(&Scrypt),
+            HashAlgorithm::Pbkdf2Sha256 => phc(&Pbkdf2),
+            HashAlgorithm::Sha512Crypt => {
+                let setup = pwhash::HashSetup { salt: None, rounds: Some(SHA512_CRYPT_ROUNDS) };
+                crypt(pwhash::sha512_crypt::hash_with(setup, password))
+            }
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = PasswordError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let algorithm = HashAlgorithm::ALL.into_iter().find(|a| a.as_str() == s);
+        algorithm.ok_or_else(|| PasswordError::UnsupportedAlgorithm(s.into()))
+    }
+}
+
+// PasswordHasher is not object safe, so hashers with different parameter types are erased here.
+trait PasswordHasherDyn {
+    fn hash(&self, password: &[u8], salt: &SaltString) -> argon2::password_hash::Result<String>;
+}
+
+impl<T: PasswordHasher> PasswordHasherDyn for T {
+    fn hash(&self, password: &[u8], salt: &SaltString) -> argon2::password_hash::Result<String> {
+        self.hash_password(password, salt).map(|h| h.to_string())
+    }
+}
+
+fn crypt_matches(password: &str, hash: &str) -> Result<bool, PasswordError> {
+    let actual = pwhash::unix::crypt(password, hash)
+        .map_err(|err| PasswordError::InvalidCryptHash(err.to_string()))?;
+    Ok(actual.as_bytes().ct_eq(hash.as_bytes()).into())
 }
 
-pub fn verify_password(
-    users: HashMap<String, String>,
-    username: &str,
-    password: &str,
-) -> Result<bool, PasswordError> {
-    if users.is_empty() {
-        return Err(PasswordError::EmptyUserList);
+// The algorithm and cost of bcrypt and SHA-512 crypt hashes, which are not in PHC format.
+fn crypt_cost(hash: &str) -> Option<(HashAlgorithm, u32)> {
+    let bcrypt = ["$2a$", "$2b$", "$2y$"].iter().find_map(|prefix| hash.strip_prefix(prefix));
+    if let Some(rest) = bcrypt {
+        let cost = rest.get(..2)?.parse().ok()?;
+        return Some((HashAlgorithm::Bcrypt, cost));
     }
+    let rest = hash.strip_prefix("$6$")?;
+    let rounds = match rest.strip_prefix("rounds=") {
+        Some(rest) => rest.split('$').next()?.parse().ok()?,
+        None => 5000,
+    };
+    Some((HashAlgorithm::Sha512Crypt, rounds))
+}
+
+// Django stores PBKDF2 hashes as `pbkdf2_sha256$<iterations>$<salt>$<base64 hash>`.
+fn django_pbkdf2_matches(password: &str, hash: &str) -> Result<bool, PasswordError> {
+    let invalid = || PasswordError::InvalidCryptHash("malformed Django PBKDF2 hash".into());
+    let mut parts = hash.splitn(4, '$').skip(1);
+    let (Some(iterations), Some(salt), Some(expected)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    let iterations = iterations.parse().map_err(|_| invalid())?;
+    let mut buf = [0u8; 64];
+    let expected = Base64::decode(expected, &mut buf).map_err(|_| invalid())?;
+    if expected.len() < DJANGO_PBKDF2_MIN_LEN {
+        return Err(invalid());
+    }
+    let mut actual = vec![0u8; expected.len()];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt.as_bytes(), iterations, &mut actual);
+    Ok(actual.ct_eq(expected).into())
+}
+
+// Argon2 hashes made with a pepper name it in their `keyid` parameter.
+fn argon2_verifier<'a>(hash: &str, peppers: &'a [Pepper]) -> Result<Argon2<'a>, PasswordError> {
+    let hash = PasswordHash::new(hash).map_err(PasswordError::InvalidPasswordHash)?;
+    let params = Params::try_from(&hash).map_err(PasswordError::InvalidPasswordHash)?;
+    if params.keyid().is_empty() {
+        return Ok(Argon2::default());
+    }
+    let Some(pepper) = peppers.iter().find(|p| p.id.as_bytes() == params.keyid()) else {
+        let id = String::from_utf8_lossy(params.keyid());
+        return Err(PasswordError::UnknownPepper(id.into()));
+    };
+    Argon2::new_with_secret(&pepper.secret, Algorithm::default(), Version::default(), params)
+        .map_err(|err| PasswordError::HashingFailed(err.into()))
+}
 
-    let entry = users.get_key_value(username).unwrap_or(users.iter().next().unwrap());
-    let hash = PasswordHash::new(entry.1).map_err(PasswordError::InvalidPasswordHash)?;
-    match Argon2::default().verify_password(password.as_bytes(), &hash) {
-        Ok(()) => Ok(entry.0 == username),
+// Dispatches on the PHC identifier (`$argon2id$`, `$scrypt$`, `$pbkdf2-sha256$`), the
+// modular crypt prefix (`$2b$`, `$5$`, `$6$`) or Django's `pbkdf2_sha256$` prefix.
+fn verify_hash(password: &str, hash: &str, peppers: &[Pepper]) -> Result<bool, PasswordError> {
+    if hash.starts_with("pbkdf2_sha256$") {
+        return django_pbkdf2_matches(password, hash);
+    }
+    let id = hash.strip_prefix('$').and_then(|rest| rest.split('$').next()).unwrap_or_default();
+    let argon2;
+    let verifier: &dyn PasswordVerifier = match id {
+        "2a" | "2b" | "2y" | "5" | "6" => return crypt_matches(password, hash),
+        "argon2id" | "argon2i" | "argon2d" => {
+            argon2 = argon2_verifier(hash, peppers)?;
+            &argon2
+        }
+        "scrypt" => &Scrypt,
+        "pbkdf2" | "pbkdf2-sha256" | "pbkdf2-sha512" => &Pbkdf2,
+        "" => {
+            let err = argon2::password_hash::Error::PhcStringField;
+            return Err(PasswordError::InvalidPasswordHash(err));
+        }
+        id => return Err(PasswordError::UnsupportedAlgorithm(id.into())),
+    };
+    let hash = PasswordHash::new(hash).map_err(PasswordError::InvalidPasswordHash)?;
+    match verifier.verify_password(password.as_bytes(), &hash) {
+        Ok(()) => Ok(true),
         Err(argon2::password_hash::Error::Password) => Ok(false),
         Err(err) => Err(PasswordError::InvalidPasswordHash(err)),
     }
 }
 
+// Verifies passwords against the user table. Unknown users are checked against a dummy hash
+// computed with the configured parameters, so that they take as long as known users. New
+// hashes use the first pepper, while the others are kept to verify older hashes.
+#[derive(Debug, Clone)]
+pub struct Verifier {
+    algorithm: HashAlgorithm,
+    params: Params,
+    peppers: Vec<Pepper>,
+    dummy_hash: Arc<str>,
+}
+
+impl Verifier {
+    pub fn new(
+        algorithm: HashAlgorithm,
+        params: Params,
+        peppers: Vec<Pepper>,
+    ) -> Result<Self, PasswordError> {
+        let mut password = [0u8; 16];
+        OsRng.fill_bytes(&mut password);
+        let dummy_hash = algorithm.hash(&hex::encode(password), &params, peppers.first())?;
+        Ok(Self { algorithm, params, peppers, dummy_hash: dummy_hash.into() })
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, PasswordError> {
+        self.algorithm.hash(password, &self.params, self.peppers.first())
+    }
+
+    // Whether the hash uses another algorithm, weaker parameters or another pepper than
+    // configured. Such hashes also take a different time to verify than the dummy hash, so
+    // an attacker can tell their users from unknown ones until they are rehashed.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        if let Some((algorithm, cost)) = crypt_cost(hash) {
+            let configured = match algorithm {
+                HashAlgorithm::Bcrypt => BCRYPT_COST,
+                _ => SHA512_CRYPT_ROUNDS,
+            };
+            return algorithm != self.algorithm || cost < configured;
+        }
+        let Ok(hash) = PasswordHash::new(hash) else {
+            return true;
+        };
+        if hash.algorithm.as_str() != self.algorithm.as_str() {
+            return true;
+        }
+        match self.algorithm {
+            HashAlgorithm::Scrypt => match scrypt::Params::try_from(&hash) {
+                Ok(params) => params.log_n() < scrypt::Params::RECOMMENDED_LOG_N,
+                Err(_) => true,
+            },
+            HashAlgorithm::Pbkdf2Sha256 => match pbkdf2::Params::try_from(&hash) {
+                Ok(params) => params.rounds < pbkdf2::Params::RECOMMENDED_ROUNDS as u32,
+                Err(_) => true,
+            },
+            _ if !self.algorithm.is_argon2() => false,
+            _ => self.argon2_needs_rehash(&hash),
+        }
+    }
+
+    fn argon2_needs_rehash(&self, hash: &PasswordHash) -> bool {
+        if hash.version != Some(Version::V0x13.into()) {
+            return true;
+        }
+        let Ok(params) = Params::try_from(hash) else {
+            return true;
+        };
+        let keyid = self.peppers.first().map(|p| p.id.as_bytes()).unwrap_or_default();
+        params.m_cost() < self.params.m_cost()
+            || params.t_cost() < self.params.t_cost()
+            || params.p_cost() < self.params.p_cost()
+            || params.keyid() != keyid
+    }
+
+    pub fn verify(
+        &self,
+        users: &HashMap<String, String>,
+        username: &str,
+        password: &str,
+    ) -> Result<bool, PasswordError> {
+        match users.get(username) {
+            Some(hash) => verify_hash(password, hash, &self.peppers),
+            None => verify_hash(password, &self.dummy_hash, &self.peppers).map(|_| false),
+        }
+    }
+}
+
+impl Default for Verifier {
+    fn default() -> Self {
+        Self::new(HashAlgorithm::Argon2id, Params::default(), vec![])
+            .expect("could not compute dummy password hash")
+    }
+}
+
 pub fn hash_password(password: &str) -> Result<String, PasswordError> {
-    let argon2 = Argon2::default();
-    let salt = SaltString::generate(&mut OsRng);
-    argon2
-        .hash_password(password.as_bytes(), &salt)
-        .map(|h| h.to_string())
-        .map_err(PasswordError::HashingFailed)
+    HashAlgorithm::Argon2id.hash(password, &Params::default(), None)
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::{Duration, Instant};
+
     use super::*;
 
+    fn test_params(t_cost: u32) -> Params {
+        Params::new(256, t_cost, 1, None).unwrap()
+    }
+
+    // Median verification time of a wrong password for each username, sampled alternately.
+    fn median_times(verifier: &Verifier, users: &HashMap<String, String>) -> [Duration; 2] {
+        let usernames = ["user", "nobody"];
+        let mut samples = [vec![], vec![]];
+        for _ in 0..200 {
+            for (username, samples) in usernames.iter().zip(samples.iter_mut()) {
+                let start = Instant::now();
+                assert_eq!(Ok(false), verifier.verify(users, username, "wrong-p@ssw0rd"));
+                samples.push(start.elapsed());
+            }
+        }
+        samples.map(|mut samples| {
+            samples.sort();
+            samples[samples.len() / 2]
+        })
+    }
+
+    fn relative_difference([a, b]: [Duration; 2]) -> f64 {
+        (a.as_secs_f64() - b.as_secs_f64()).abs() / a.max(b).as_secs_f64()
+    }
+
     #[test]
     fn test_hash_password_ok() {
         let actual = hash_password("p@ssw0rd");
@@ -57,7 +402,7 @@ mod tests {
         let users: HashMap<String, String> =
             [("user".into(), hash_password("p@ssw0rd").unwrap())].into();
         let expected = Ok(true);
-        let actual = verify_password(users, "user", "p@ssw0rd");
+        let actual = Verifier::default().verify(&users, "user", "p@ssw0rd");
         assert_eq!(expected, actual);
     }
 
@@ -66,7 +411,7 @@ mod tests {
         let users: HashMap<String, String> =
             [("user".into(), hash_password("p@ssw0rd").unwrap())].into();
         let expected = Ok(false);
-        let actual = verify_password(users, "user", "wrong-p@ssw0rd");
+        let actual = Verifier::default().verify(&users, "user", "wrong-p@ssw0rd");
         assert_eq!(expected, actual);
     }
 
@@ -75,14 +420,118 @@ mod tests {
         let users: HashMap<String, String> =
             [("user".into(), hash_password("p@ssw0rd").unwrap())].into();
         let expected = Ok(false);
-        let actual = verify_password(users, "wrong-user", "p@ssw0rd");
+        let actual = Verifier::default().verify(&users, "wrong-user", "p@ssw0rd");
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_verify_password_no_users() {
+        let expected = Ok(false);
+        let actual = Verifier::default().verify(&HashMap::new(), "user", "p@ssw0rd");
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn test_verify_password_invalid_hash() {
         let users = [("user".into(), "invalid".into())].into();
-        let actual = verify_password(users, "user", "p@ssw0rd");
+        let actual = Verifier::default().verify(&users, "user", "p@ssw0rd");
         assert!(matches!(actual, Err(PasswordError::InvalidPasswordHash(_))));
     }
+
+    // Compares wall-clock times, so it is only reliable on an otherwise idle machine.
+    #[test]
+    #[ignore = "timing sensitive, run with --ignored --test-threads=1"]
+    fn test_verify_password_unknown_user_timing() {
+        let argon2 = HashAlgorithm::Argon2id;
+        let verifier = Verifier::new(argon2, test_params(2), vec![]).unwrap();
+        let users =
+            [("user".into(), argon2.hash("p@ssw0rd", &test_params(2), None).unwrap())].into();
+        let times = median_times(&verifier, &users);
+        assert!(relative_difference(times) < 0.15, "{:?}", times);
+
+        // Sanity check that the comparison notices a cost difference.
+        let users =
+            [("user".into(), argon2.hash("p@ssw0rd", &test_params(8), None).unwrap())].into();
+        let times = median_times(&verifier, &users);
+        assert!(relative_difference(times) > 0.5, "{:?}", times);
+    }
+
+    #[test]
+    fn test_verify_password_imported_hashes() {
+        let hashes = [
+            "$2y$05$bvIG6Nmid91Mu9RcmmWZfO5HJIMCT8riNW0hEp8f6/FuA2/mHZFpe",
+            "$5$saltsalt$U9.bfN20jxxU/N.KRyp0q6wCImrhEyZXRHSxdYkWYN2",
+            "$6$rounds=5000$saltsaltsalt$vkys6EnR86EM36lrDALUGsCHVs/1o66kM2qZo1CykooqisCR9QdVXAZNA1dOxWR3Q8VJUKrHlAodqaoKSsezu.",
+            "pbkdf2_sha256$1000$seasalt$Ct1LhKwHRy70kFSNQPNOcrZkExl+bUTgJPa7OLal4Dw=",
+            "$pbkdf2-sha256$i=1000,l=32$c2Vhc2FsdA$Ct1LhKwHRy70kFSNQPNOcrZkExl+bUTgJPa7OLal4Dw",
+        ];
+        let passwords = ["password", "p@ssw0rd", "p@ssw0rd", "p@ssw0rd", "p@ssw0rd"];
+        for (hash, password) in hashes.into_iter().zip(passwords) {
+            assert_eq!(Ok(true), verify_hash(password, hash, &[]), "{}", hash);
+            assert_eq!(Ok(false), verify_hash("wrong-p@ssw0rd", hash, &[]), "{}", hash);
+        }
+    }
+
+    #[test]
+    fn test_hash_algorithms() {
+        for algorithm in HashAlgorithm::ALL {
+            let hash = algorithm.hash("p@ssw0rd", &Params::default(), None).unwrap();
+            assert_eq!(Ok(true), verify_hash("p@ssw0rd", &hash, &[]), "{}", hash);
+        }
+    }
+
+    #[test]
+    fn test_verify_password_pepper() {
+        let old = Pepper::new("1", b"old-secret".to_vec()).unwrap();
+        let new = Pepper::new("2", b"new-secret".to_vec()).unwrap();
+        let hash = HashAlgorithm::Argon2id.hash("p@ssw0rd", &test_params(1), Some(&old)).unwrap();
+        assert!(hash.contains("keyid=MQ"), "{}", hash);
+
+        let users = [("user".into(), hash.clone())].into();
+        let verifier = Verifier::new(HashAlgorithm::Argon2id, test_params(1), vec![new, old]);
+        let verifier = verifier.unwrap();
+        assert_eq!(Ok(true), verifier.verify(&users, "user", "p@ssw0rd"));
+        assert_eq!(Ok(false), verifier.verify(&users, "user", "wrong-p@ssw0rd"));
+        assert!(verifier.needs_rehash(&hash));
+        assert!(!verifier.needs_rehash(&verifier.hash("p@ssw0rd").unwrap()));
+
+        let expected = Err(PasswordError::UnknownPepper("1".into()));
+        assert_eq!(expected, Verifier::default().verify(&users, "user", "p@ssw0rd"));
+        assert_eq!(Ok(false), verify_hash("p@ssw0rd", &hash.replace(",keyid=MQ", ""), &[]));
+    }
+
+    #[test]
+    fn test_verify_password_short_django_hash() {
+        let invalid =
+            || Err(PasswordError::InvalidCryptHash("malformed Django PBKDF2 hash".into()));
+        assert_eq!(invalid(), verify_hash("p@ssw0rd", "pbkdf2_sha256$1000$seasalt$", &[]));
+        assert_eq!(invalid(), verify_hash("p@ssw0rd", "pbkdf2_sha256$1000$seasalt$Ct1LhA==", &[]));
+    }
+
+    #[test]
+    fn test_needs_rehash_imported_hashes() {
+        let hashes = [
+            "$2y$05$bvIG6Nmid91Mu9RcmmWZfO5HJIMCT8riNW0hEp8f6/FuA2/mHZFpe",
+            "$6$rounds=5000$saltsaltsalt$vkys6EnR86EM36lrDALUGsCHVs/1o66kM2qZo1CykooqisCR9QdVXAZNA1dOxWR3Q8VJUKrHlAodqaoKSsezu.",
+            "pbkdf2_sha256$1000$seasalt$Ct1LhKwHRy70kFSNQPNOcrZkExl+bUTgJPa7OLal4Dw=",
+            "$pbkdf2-sha256$i=1000,l=32$c2Vhc2FsdA$Ct1LhKwHRy70kFSNQPNOcrZkExl+bUTgJPa7OLal4Dw",
+        ];
+        for algorithm in
+            [HashAlgorithm::Bcrypt, HashAlgorithm::Sha512Crypt, HashAlgorithm::Pbkdf2Sha256]
+        {
+            let verifier = Verifier::new(algorithm, Params::default(), vec![]).unwrap();
+            for hash in hashes {
+                assert!(verifier.needs_rehash(hash), "{} {}", algorithm, hash);
+            }
+            let hash = verifier.hash("p@ssw0rd").unwrap();
+            assert!(!verifier.needs_rehash(&hash), "{}", hash);
+        }
+    }
+
+    #[test]
+    fn test_verify_password_unsupported() {
+        let expected = Err(PasswordError::UnsupportedAlgorithm("1".into()));
+        assert_eq!(expected, verify_hash("password", "$1$5pZSV9va$azfrPr6af3Fc7dLblQXVa0", &[]));
+        assert!("md5".parse::<HashAlgorithm>().is_err());
+    }
 }
@@ -0,0 +1,236 @@
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::header::FORWARDED;
+use axum::http::request::Parts;
+use axum::http::HeaderMap;
+use ipnet::IpNet;
+use serde::Deserialize;
+
+use super::connection::ConnectionInfo;
+
+const X_FORWARDED_FOR: &str = "X-Forwarded-For";
+
+// The header that trusted proxies append the client address to. Only that header is read, as a
+// client can send the other one with any address it likes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum ForwardedHeader {
+    #[serde(rename = "forwarded")]
+    Forwarded,
+    #[default]
+    #[serde(rename = "x-forwarded-for")]
+    XForwardedFor,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    pub networks: Vec<IpNet>,
+    pub header: ForwardedHeader,
+    // Connections over a unix socket have no address, so whether their peers are proxies is
+    // configured separately. Any local process that can open the socket is trusted when set.
+    pub unix_socket: bool,
+}
+
+impl TrustedProxies {
+    pub fn is_trusted(&self, address: Option<IpAddr>) -> bool {
+        match address {
+            Some(addr) => self.networks.iter().any(|net| net.contains(&addr)),
+            None => self.unix_socket,
+        }
+    }
+}
+
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(addr) = node.parse::<IpAddr>() {
+        return Some(addr);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+}
+
+fn forwarded_for(headers: &HeaderMap) -> Vec<&str> {
+    headers
+        .get_all(FORWARDED)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim().eq_ignore_ascii_case("for").then_some(value)
+            })
+        })
+        .collect()
+}
+
+fn x_forwarded_for(headers: &HeaderMap) -> Vec<&str> {
+    headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect()
+}
+
+pub fn resolve_client_address(
+    peer: Option<IpAddr>,
+    headers: &HeaderMap,
+    trusted: &TrustedProxies,
+) -> Option<IpAddr> {
+    let hops = match trusted.header {
+        ForwardedHeader::Forwarded => forwarded_for(headers),
+        ForwardedHeader::XForwardedFor => x_forwarded_for(headers),
+    };
+    let mut client = peer;
+    for hop in hops.into_iter().rev() {
+        if !trusted.is_trusted(client) {
+            break;
+        }
+        match parse_node(hop) {
+            Some(addr) => client = Some(addr),
+            None => break,
+        }
+    }
+    client
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ClientAddress(pub Option<IpAddr>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientAddress
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    // The trusted proxies come from the configuration snapshot that the router loads for the
+    // request. Without one, forwarded headers are ignored.
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<ConnectionInfo>>()
+            .and_then(|ConnectInfo(info)| info.peer_address)
+            .map(|addr| addr.ip());
+        let trusted = parts.extensions.get::<TrustedProxies>().cloned().unwrap_or_default();
+        Ok(ClientAddress(resolve_client_address(peer, &parts.headers, &trusted)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trusted(nets: &[&str]) -> TrustedProxies {
+        let networks = nets.iter().map(|n| n.parse().unwrap()).collect();
+        TrustedProxies { networks, header: ForwardedHeader::XForwardedFor, unix_socket: false }
+    }
+
+    fn trusted_forwarded(nets: &[&str]) -> TrustedProxies {
+        TrustedProxies { header: ForwardedHeader::Forwarded, ..trusted(nets) }
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (key, value) in pairs {
+            headers.append(*key, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn ip(addr: &str) -> Option<IpAddr> {
+        Some(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn test_resolve_client_address_untrusted_peer() {
+        let headers = headers(&[(X_FORWARDED_FOR, "192.0.2.1")]);
+        let expected = ip("203.0.113.1");
+        let actual = resolve_client_address(ip("203.0.113.1"), &headers, &trusted(&["10.0.0.0/8"]));
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_resolve_client_address_trusted_peer() {
+        let headers = headers(&[(X_FORWARDED_FOR, "192.0.2.1")]);
+        let expected = ip("192.0.2.1");
+        let actual = resolve_client_address(ip("10.0.0.1"), &headers, &trusted(&["10.0.0.0/8"]));
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_resolve_client_address_stops_at_untrusted_hop() {
+        let headers = headers(&[(X_FORWARDED_FOR, "198.51.100.7, 192.0.2.1, 10.0.0.2")]);
+        let expected = ip("192.0.2.1");
+        let actual = resolve_client_address(ip("10.0.0.1"), &headers, &trusted(&["10.0.0.0/8"]));
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_resolve_client_address_multiple_headers() {
+        let headers = headers(&[(X_FORWARDED_FOR, "192.0.2.1"), (X_FORWARDED_FOR, "10.0.0.2")]);
+        let expected = ip("192.0.2.1");
+        let actual = resolve_client_address(ip("10.0.0.1"), &headers, &trusted(&["10.0.0.0/8"]));
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_resolve_client_address_forwarded() {
+        let headers = headers(&[(
+            "Forwarded",
+            "for=192.0.2.1;proto=https, For=\"[2001:db8::1]:4711\";by=10.0.0.1",
+        )]);
+        let expected = ip("2001:db8::1");
+        let actual =
+            resolve_client_address(ip("10.0.0.1"), &headers, &trusted_forwarded(&["10.0.0.0/8"]));
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_resolve_client_address_forwarded_obfuscated() {
+        let headers = headers(&[("Forwarded", "for=_hidden")]);
+        let expected = ip("10.0.0.1");
+        let actual =
+            resolve_client_address(ip("10.0.0.1"), &headers, &trusted_forwarded(&["10.0.0.0/8"]));
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_resolve_client_address_both_headers() {
+        let headers =
+            headers(&[("Forwarded", "for=192.0.2.66"), (X_FORWARDED_FOR, "198.51.100.7")]);
+        let trusted_nets = ["10.0.0.0/8"];
+
+        let expected = ip("198.51.100.7");
+        let actual = resolve_client_address(ip("10.0.0.1"), &headers, &trusted(&trusted_nets));
+        assert_eq!(expected, actual);
+        let expected = ip("192.0.2.66");
+        let actual =
+            resolve_client_address(ip("10.0.0.1"), &headers, &trusted_forwarded(&trusted_nets));
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_resolve_client_address_ignores_other_header() {
+        let headers = headers(&[("Forwarded", "for=192.0.2.66")]);
+        let expected = ip("10.0.0.1");
+        let actual = resolve_client_address(ip("10.0.0.1"), &headers, &trusted(&["10.0.0.0/8"]));
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_resolve_client_address_unix_socket() {
+        let headers = headers(&[(X_FORWARDED_FOR, "192.0.2.1:1234")]);
+        assert_eq!(None, resolve_client_address(None, &headers, &trusted(&[])));
+
+        let trusted = TrustedProxies { unix_socket: true, ..trusted(&[]) };
+        let expected = ip("192.0.2.1");
+        let actual = resolve_client_address(None, &headers, &trusted);
+        assert_eq!(expected, actual);
+    }
+}
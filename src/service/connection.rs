@@ -0,0 +1,7 @@
+use std::net::SocketAddr;
+
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionInfo {
+    pub peer_address: Option<SocketAddr>,
+    pub client_certificate_names: Vec<String>,
+}
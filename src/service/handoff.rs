@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{DateTime, Utc};
+
+type UtcDateTime = DateTime<Utc>;
+
+const CODE_LENGTH: usize = 32;
+
+// The realm and user entry that a code was issued for, so that the code only signs the user in
+// to realms that share the entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandoffGrant {
+    pub subject: String,
+    pub realm: String,
+    pub password_hash: String,
+}
+
+#[derive(Debug, Clone)]
+struct HandoffEntry {
+    grant: HandoffGrant,
+    domain: String,
+    expires_at: UtcDateTime,
+}
+
+#[derive(Debug, Clone)]
+pub struct HandoffStore {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<String, HandoffEntry>>>,
+}
+
+impl HandoffStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl, entries: Default::default() }
+    }
+
+    pub fn issue(&self, grant: HandoffGrant, domain: &str, now: Option<UtcDateTime>) -> String {
+        let now = now.unwrap_or_else(Utc::now);
+        let mut bytes = [0u8; CODE_LENGTH];
+        OsRng.fill_bytes(&mut bytes);
+        let code = hex::encode(bytes);
+        let entry =
+            HandoffEntry { grant, domain: domain.to_ascii_lowercase(), expires_at: now + self.ttl };
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, e| e.expires_at >= now);
+        entries.insert(code.clone(), entry);
+        code
+    }
+
+    pub fn redeem(
+        &self,
+        code: &str,
+        domain: &str,
+        now: Option<UtcDateTime>,
+    ) -> Option<HandoffGrant> {
+        let now = now.unwrap_or_else(Utc::now);
+        let entry = self.entries.lock().unwrap().remove(code)?;
+        let valid = entry.expires_at >= now && entry.domain.eq_ignore_ascii_case(domain);
+        valid.then_some(entry.grant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestamp(secs: i64) -> UtcDateTime {
+        UtcDateTime::from_timestamp(secs, 0).unwrap()
+    }
+
+    fn grant() -> HandoffGrant {
+        HandoffGrant { subject: "user".into(), realm: "default".into(), password_hash: "h".into() }
+    }
+
+    #[test]
+    fn test_redeem_ok() {
+        let store = HandoffStore::new(Duration::from_secs(30));
+        let code = store.issue(grant(), "app.example.com", Some(timestamp(100000)));
+        let expected = Some(grant());
+        let actual = store.redeem(&code, "APP.example.com", Some(timestamp(100000 + 30)));
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_redeem_single_use() {
+        let store = HandoffStore::new(Duration::from_secs(30));
+        let code = store.issue(grant(), "app.example.com", Some(timestamp(100000)));
+        assert!(store.redeem(&code, "app.example.com", Some(timestamp(100000))).is_some());
+        assert!(store.redeem(&code, "app.example.com", Some(timestamp(100000))).is_none());
+    }
+
+    #[test]
+    fn test_redeem_expired() {
+        let store = HandoffStore::new(Duration::from_secs(30));
+        let code = store.issue(grant(), "app.example.com", Some(timestamp(100000)));
+        let actual = store.redeem(&code, "app.example.com", Some(timestamp(100000 + 31)));
+        assert_eq!(None, actual);
+    }
+
+    #[test]
+    fn test_redeem_wrong_domain() {
+        let store = HandoffStore::new(Duration::from_secs(30));
+        let code = store.issue(grant(), "app.example.com", Some(timestamp(100000)));
+        assert_eq!(None, store.redeem(&code, "evil.example.com", Some(timestamp(100000))));
+        assert_eq!(None, store.redeem(&code, "app.example.com", Some(timestamp(100000))));
+    }
+
+    #[test]
+    fn test_redeem_unknown_code() {
+        let store = HandoffStore::new(Duration::from_secs(30));
+        assert_eq!(None, store.redeem("unknown", "app.example.com", None));
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::sync::Semaphore;
+use tokio::task::JoinError;
+
+use super::access_log::with_request_id_blocking;
+
+#[derive(Debug, Error)]
+pub enum HashingError {
+    #[error("hashing queue is full")]
+    QueueFull,
+    #[error("timed out waiting for a hashing slot")]
+    QueueTimeout,
+    #[error("hashing task failed: {0}")]
+    TaskFailed(JoinError),
+}
+
+// Runs password hashing on the blocking pool so that it cannot starve the async workers.
+#[derive(Debug, Clone)]
+pub struct HashingPool {
+    permits: Arc<Semaphore>,
+    waiting: Arc<AtomicUsize>,
+    queue_size: usize,
+    queue_timeout: Duration,
+}
+
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl HashingPool {
+    pub fn new(concurrency: usize, queue_size: usize, queue_timeout: Duration) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(concurrency)),
+            waiting: Default::default(),
+            queue_size,
+            queue_timeout,
+        }
+    }
+
+    pub async fn run<T, F>(&self, f: F) -> Result<T, HashingError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let permit = match self.permits.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                if self.waiting.fetch_add(1, Ordering::Relaxed) >= self.queue_size {
+                    self.waiting.fetch_sub(1, Ordering::Relaxed);
+                    return Err(HashingError::QueueFull);
+                }
+                let _waiting = Waiting(&self.waiting);
+                let acquire = self.permits.clone().acquire_owned();
+                match tokio::time::timeout(self.queue_timeout, acquire).await {
+                    Ok(permit) => permit.expect("hashing semaphore closed"),
+                    Err(_) => return Err(HashingError::QueueTimeout),
+                }
+            }
+        };
+        tokio::task::spawn_blocking(with_request_id_blocking(move || {
+            let result = f();
+            drop(permit);
+            result
+        }))
+        .await
+        .map_err(HashingError::TaskFailed)
+    }
+}
+
+impl Default for HashingPool {
+    fn default() -> Self {
+        let concurrency = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::new(concurrency, concurrency * 4, Duration::from_secs(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run() {
+        let pool = HashingPool::new(1, 1, Duration::from_secs(1));
+        assert_eq!(42, pool.run(|| 42).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_run_saturated() {
+        let pool = HashingPool::new(1, 1, Duration::from_millis(100));
+        let (sender, receiver) = mpsc::channel::<()>();
+        let busy = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(move || receiver.recv().unwrap()).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let queued = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(|| ()).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(matches!(pool.run(|| ()).await, Err(HashingError::QueueFull)));
+        assert!(matches!(queued.await.unwrap(), Err(HashingError::QueueTimeout)));
+
+        sender.send(()).unwrap();
+        busy.await.unwrap().unwrap();
+        assert!(pool.run(|| ()).await.is_ok());
+    }
+}
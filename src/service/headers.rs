@@ -1,2 +1,3 @@
 pub const X_AUTH_REQUEST_USER: &str = "X-Auth-Request-User";
 pub const X_AUTH_REQUEST_REDIRECT: &str = "X-Auth-Request-Redirect";
+pub const X_REQUEST_ID: &str = "X-Request-Id";
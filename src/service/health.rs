@@ -0,0 +1,75 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use serde_json::json;
+
+use super::router::{CurrentConfig, ServiceConfig};
+
+#[derive(Debug, Clone, Default)]
+pub struct Draining(Arc<AtomicBool>);
+
+impl Draining {
+    pub fn start(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub fn check_readiness(config: &ServiceConfig) -> Result<(), &'static str> {
+    if config.draining.is_draining() {
+        return Err("draining");
+    }
+    if config.realms.iter().all(|realm| realm.users.is_empty()) {
+        return Err("no_users");
+    }
+    Ok(())
+}
+
+pub async fn healthz() -> impl IntoResponse {
+    Json::from(json!({"status": "ok"}))
+}
+
+pub async fn readyz(CurrentConfig(config): CurrentConfig) -> impl IntoResponse {
+    match check_readiness(&config) {
+        Ok(()) => (StatusCode::OK, Json::from(json!({"status": "ready"}))),
+        Err(reason) => {
+            log::warn!("not ready: {}", reason);
+            let body = json!({"status": "not_ready", "reason": reason});
+            (StatusCode::SERVICE_UNAVAILABLE, Json::from(body))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(users: &[&str]) -> ServiceConfig {
+        ServiceConfig::for_tests(users.iter().map(|u| (u.to_string(), String::new())).collect())
+    }
+
+    #[test]
+    fn test_check_readiness_ok() {
+        let config = config(&["user"]);
+        assert_eq!(Ok(()), check_readiness(&config));
+    }
+
+    #[test]
+    fn test_check_readiness_draining() {
+        let config = config(&["user"]);
+        config.draining.start();
+        assert_eq!(Err("draining"), check_readiness(&config));
+    }
+
+    #[test]
+    fn test_check_readiness_no_users() {
+        let config = config(&[]);
+        assert_eq!(Err("no_users"), check_readiness(&config));
+    }
+}
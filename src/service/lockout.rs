@@ -0,0 +1,429 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::num::NonZeroUsize;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use fd_lock::RwLock;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+type UtcDateTime = DateTime<Utc>;
+
+const MAX_UNKNOWN_ACCOUNTS: NonZeroUsize = match NonZeroUsize::new(10_000) {
+    Some(n) => n,
+    None => unreachable!(),
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LockoutPolicy {
+    pub max_failures: u32,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Account {
+    failures: u32,
+    locked_until: Option<UtcDateTime>,
+}
+
+type Accounts = BTreeMap<String, BTreeMap<String, Account>>;
+
+#[derive(Debug, Clone)]
+enum Change {
+    Failure { realm: String, username: String, at: UtcDateTime },
+    Success { realm: String, username: String },
+}
+
+#[derive(Debug)]
+struct State {
+    accounts: Accounts,
+    // Failures of usernames that do not exist, which lock out like real accounts so that the
+    // responses do not reveal which usernames exist. They are only kept in memory.
+    unknown: LruCache<(String, String), Account>,
+    // Changes made since the state file was last written.
+    pending: Vec<Change>,
+    // Version of the state file when it was last read or written.
+    version: Option<FileVersion>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            accounts: Default::default(),
+            unknown: LruCache::new(MAX_UNKNOWN_ACCOUNTS),
+            pending: vec![],
+            version: None,
+        }
+    }
+}
+
+// Accounts are checked and updated in memory. `sync` merges the pending changes into the state
+// file and picks up changes made by other processes, such as `unlock`.
+#[derive(Debug, Clone)]
+pub struct LockoutStore {
+    policy: LockoutPolicy,
+    path: Option<PathBuf>,
+    state: Arc<Mutex<State>>,
+}
+
+// Returns the end of the lockout when the failure locks the account.
+fn fail(policy: &LockoutPolicy, account: &mut Account, at: UtcDateTime) -> Option<UtcDateTime> {
+    if account.locked_until.is_some_and(|until| until <= at) {
+        *account = Account::default();
+    }
+    account.failures += 1;
+    if account.failures < policy.max_failures || account.locked_until.is_some() {
+        return None;
+    }
+    let until = at + policy.duration;
+    account.locked_until = Some(until);
+    Some(until)
+}
+
+// Returns the end of the lockout when the change locks the account.
+fn apply(policy: &LockoutPolicy, accounts: &mut Accounts, change: &Change) -> Option<UtcDateTime> {
+    match change {
+        Change::Failure { realm, username, at } => {
+            let users = accounts.entry(realm.clone()).or_default();
+            fail(policy, users.entry(username.clone()).or_default(), *at)
+        }
+        Change::Success { realm, username } => {
+            if let Some(users) = accounts.get_mut(realm) {
+                users.remove(username);
+            }
+            accounts.retain(|_, users| !users.is_empty());
+            None
+        }
+    }
+}
+
+// Other processes are excluded with a lock on a separate file, as the state file itself is
+// replaced on every write.
+fn lock_file(path: &Path) -> io::Result<RwLock<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path.with_extension("lock"))?;
+    Ok(RwLock::new(file))
+}
+
+// Identifies a version of the state file. Each write replaces the file, so its inode changes
+// even when the modification time does not.
+type FileVersion = (u64, SystemTime, u64);
+
+fn file_version(path: &Path) -> io::Result<Option<FileVersion>> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(Some((metadata.ino(), metadata.modified()?, metadata.len()))),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn read_accounts(path: &Path) -> io::Result<Accounts> {
+    match fs::read(path) {
+        Ok(content) => Ok(serde_json::from_slice(&content)?),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Default::default()),
+        Err(err) => Err(err),
+    }
+}
+
+fn write_accounts(path: &Path, accounts: &Accounts) -> io::Result<()> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut temp = NamedTempFile::new_in(dir)?;
+    temp.write_all(&serde_json::to_vec_pretty(accounts)?)?;
+    temp.persist(path)?;
+    Ok(())
+}
+
+impl LockoutStore {
+    pub fn new(policy: LockoutPolicy, path: Option<PathBuf>) -> Self {
+        Self { policy, path, state: Default::default() }
+    }
+
+    pub fn disabled() -> Self {
+        Self::new(LockoutPolicy { max_failures: 0, duration: Duration::ZERO }, None)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.policy.max_failures > 0
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        self.is_enabled() && self.path.is_some()
+    }
+
+    fn record(&self, state: &mut State, change: Change) -> Option<UtcDateTime> {
+        let until = apply(&self.policy, &mut state.accounts, &change);
+        if self.path.is_some() {
+            state.pending.push(change);
+        }
+        until
+    }
+
+    pub fn locked_until(
+        &self,
+        realm: &str,
+        username: &str,
+        now: Option<UtcDateTime>,
+    ) -> Option<UtcDateTime> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let now = now.unwrap_or_else(Utc::now);
+        let state = self.state.lock().unwrap();
+        let account = match state.accounts.get(realm).and_then(|users| users.get(username)) {
+            Some(account) => Some(account),
+            None => state.unknown.peek(&(realm.to_owned(), username.to_owned())),
+        };
+        account.and_then(|a| a.locked_until).filter(|until| *until > now)
+    }
+
+    // Returns the end of the lockout when this failure locks the account.
+    pub fn record_failure(
+        &self,
+        realm: &str,
+        username: &str,
+        now: Option<UtcDateTime>,
+    ) -> Option<UtcDateTime> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let at = now.unwrap_or_else(Utc::now);
+        let mut state = self.state.lock().unwrap();
+        let change = Change::Failure { realm: realm.into(), username: username.into(), at };
+        self.record(&mut state, change)
+    }
+
+    // Like `record_failure`, for a username that does not exist.
+    pub fn record_unknown_failure(
+        &self,
+        realm: &str,
+        username: &str,
+        now: Option<UtcDateTime>,
+    ) -> Option<UtcDateTime> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let at = now.unwrap_or_else(Utc::now);
+        let mut state = self.state.lock().unwrap();
+        let key = (realm.to_owned(), username.to_owned());
+        fail(&self.policy, state.unknown.get_or_insert_mut(key, Account::default), at)
+    }
+
+    pub fn record_success(&self, realm: &str, username: &str) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.accounts.get(realm).is_some_and(|users| users.contains_key(username)) {
+            self.record(
+                &mut state,
+                Change::Success { realm: realm.into(), username: username.into() },
+            );
+        }
+    }
+
+    // Writes the pending changes to the state file and reloads it if another process changed
+    // it. This blocks, so the server calls it from a blocking task.
+    pub fn sync(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let (pending, version) = {
+            let mut state = self.state.lock().unwrap();
+            (std::mem::take(&mut state.pending), state.version)
+        };
+        let result = self.merge(path, &pending, version);
+        let mut state = self.state.lock().unwrap();
+        match result {
+            Ok(Some((mut accounts, version))) => {
+                // Changes recorded while the file was being written are already in memory.
+                for change in &state.pending {
+                    apply(&self.policy, &mut accounts, change);
+                }
+                state.accounts = accounts;
+                state.version = version;
+                Ok(())
+            }
+            Ok(None) => Ok(()),
+            Err(err) => {
+                let newer = std::mem::replace(&mut state.pending, pending);
+                state.pending.extend(newer);
+                Err(err)
+            }
+        }
+    }
+
+    fn merge(
+        &self,
+        path: &Path,
+        pending: &[Change],
+        version: Option<FileVersion>,
+    ) -> io::Result<Option<(Accounts, Option<FileVersion>)>> {
+        let mut lock = lock_file(path)?;
+        let _lock = lock.write()?;
+        if pending.is_empty() && file_version(path)? == version {
+            return Ok(None);
+        }
+        let mut accounts = read_accounts(path)?;
+        for change in pending {
+            apply(&self.policy, &mut accounts, change);
+        }
+        if !pending.is_empty() {
+            write_accounts(path, &accounts)?;
+        }
+        Ok(Some((accounts, file_version(path)?)))
+    }
+
+    // Clears the failure count of the user in the given realm, or in every realm, in the state
+    // file. A running server picks the change up on its next sync.
+    pub fn unlock(&self, realm: Option<&str>, username: &str) -> io::Result<Vec<String>> {
+        let Some(path) = &self.path else {
+            return Ok(vec![]);
+        };
+        let mut lock = lock_file(path)?;
+        let _lock = lock.write()?;
+        let mut accounts = read_accounts(path)?;
+        let mut unlocked = vec![];
+        for (name, users) in accounts.iter_mut() {
+            if realm.is_some_and(|realm| realm != name) {
+                continue;
+            }
+            if users.remove(username).is_some() {
+                unlocked.push(name.clone());
+            }
+        }
+        accounts.retain(|_, users| !users.is_empty());
+        if !unlocked.is_empty() {
+            write_accounts(path, &accounts)?;
+        }
+        Ok(unlocked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestamp(secs: i64) -> UtcDateTime {
+        UtcDateTime::from_timestamp(secs, 0).unwrap()
+    }
+
+    fn store(path: Option<PathBuf>) -> LockoutStore {
+        let policy = LockoutPolicy { max_failures: 3, duration: Duration::from_secs(600) };
+        LockoutStore::new(policy, path)
+    }
+
+    #[test]
+    fn test_lock_after_max_failures() {
+        let store = store(None);
+        let now = Some(timestamp(100000));
+        assert_eq!(None, store.record_failure("default", "alice", now));
+        assert_eq!(None, store.record_failure("default", "alice", now));
+        assert_eq!(None, store.locked_until("default", "alice", now));
+
+        let expected = Some(timestamp(100600));
+        assert_eq!(expected, store.record_failure("default", "alice", now));
+        assert_eq!(expected, store.locked_until("default", "alice", now));
+        assert_eq!(None, store.locked_until("work", "alice", now));
+        assert_eq!(None, store.record_failure("default", "alice", now));
+    }
+
+    #[test]
+    fn test_lock_unknown_user() {
+        let store = store(None);
+        let now = Some(timestamp(100000));
+        assert_eq!(None, store.record_unknown_failure("default", "mallory", now));
+        assert_eq!(None, store.record_unknown_failure("default", "mallory", now));
+        let expected = Some(timestamp(100600));
+        assert_eq!(expected, store.record_unknown_failure("default", "mallory", now));
+        assert_eq!(expected, store.locked_until("default", "mallory", now));
+        assert_eq!(None, store.locked_until("work", "mallory", now));
+        assert_eq!(None, store.locked_until("default", "mallory", Some(timestamp(100600))));
+        assert!(store.state.lock().unwrap().pending.is_empty());
+    }
+
+    #[test]
+    fn test_lock_expires() {
+        let store = store(None);
+        for _ in 0..3 {
+            store.record_failure("default", "alice", Some(timestamp(100000)));
+        }
+        let later = Some(timestamp(100600));
+        assert_eq!(None, store.locked_until("default", "alice", later));
+        assert_eq!(None, store.record_failure("default", "alice", later));
+    }
+
+    #[test]
+    fn test_success_resets_failures() {
+        let store = store(None);
+        let now = Some(timestamp(100000));
+        store.record_failure("default", "alice", now);
+        store.record_failure("default", "alice", now);
+        store.record_success("default", "alice");
+        assert_eq!(None, store.record_failure("default", "alice", now));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let store = LockoutStore::disabled();
+        for _ in 0..10 {
+            assert_eq!(None, store.record_failure("default", "alice", None));
+        }
+        assert_eq!(None, store.locked_until("default", "alice", None));
+    }
+
+    #[test]
+    fn test_persisted_and_unlocked() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lockout.json");
+        let now = Some(timestamp(100000));
+        let server = store(Some(path.clone()));
+        for _ in 0..3 {
+            server.record_failure("default", "alice", now);
+        }
+        server.sync().unwrap();
+        let restarted = store(Some(path.clone()));
+        restarted.sync().unwrap();
+        assert!(restarted.locked_until("default", "alice", now).is_some());
+
+        let unlocked = store(Some(path.clone())).unlock(None, "alice").unwrap();
+        assert_eq!(vec!["default".to_string()], unlocked);
+        assert!(server.locked_until("default", "alice", now).is_some());
+        server.sync().unwrap();
+        assert_eq!(None, server.locked_until("default", "alice", now));
+        assert!(store(Some(path)).unlock(None, "alice").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sync_merges_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lockout.json");
+        let now = Some(timestamp(100000));
+        let first = store(Some(path.clone()));
+        let second = store(Some(path.clone()));
+        first.record_failure("default", "alice", now);
+        first.record_failure("default", "bob", now);
+        first.sync().unwrap();
+        second.record_failure("default", "alice", now);
+        second.record_failure("default", "alice", now);
+        assert_eq!(None, second.locked_until("default", "alice", now));
+        second.sync().unwrap();
+        assert_eq!(Some(timestamp(100600)), second.locked_until("default", "alice", now));
+
+        store(Some(path.clone())).unlock(Some("default"), "bob").unwrap();
+        first.record_failure("default", "carol", now);
+        first.sync().unwrap();
+        assert_eq!(Some(timestamp(100600)), first.locked_until("default", "alice", now));
+        let state = first.state.lock().unwrap();
+        assert!(!state.accounts["default"].contains_key("bob"));
+        assert!(state.pending.is_empty());
+    }
+}
@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use prometheus::{
+    exponential_buckets, Histogram, HistogramOpts, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+
+const NAMESPACE: &str = "staticauth";
+
+#[derive(Debug)]
+pub struct Metrics {
+    registry: Registry,
+    pub authentications: IntCounterVec,
+    pub userinfo_requests: IntCounterVec,
+    pub session_age: Histogram,
+    pub password_verification_duration: Histogram,
+    pub configured_users: IntGaugeVec,
+    pub active_keys: IntGauge,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let authentications = IntCounterVec::new(
+            Opts::new("authentications_total", "Authentication attempts by result.")
+                .namespace(NAMESPACE),
+            &["result"],
+        )
+        .unwrap();
+        let userinfo_requests = IntCounterVec::new(
+            Opts::new("userinfo_requests_total", "Session checks by result.").namespace(NAMESPACE),
+            &["result"],
+        )
+        .unwrap();
+        let session_age = Histogram::with_opts(
+            HistogramOpts::new("session_age_seconds", "Age of sessions presented for validation.")
+                .namespace(NAMESPACE)
+                .buckets(exponential_buckets(60.0, 4.0, 10).unwrap()),
+        )
+        .unwrap();
+        let password_verification_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "password_verification_duration_seconds",
+                "Time spent verifying password hashes.",
+            )
+            .namespace(NAMESPACE)
+            .buckets(exponential_buckets(0.005, 2.0, 10).unwrap()),
+        )
+        .unwrap();
+        let configured_users = IntGaugeVec::new(
+            Opts::new("configured_users", "Number of configured users by realm.")
+                .namespace(NAMESPACE),
+            &["realm"],
+        )
+        .unwrap();
+        let active_keys = IntGauge::with_opts(
+            Opts::new("active_keys", "Number of session keys accepted for cookies.")
+                .namespace(NAMESPACE),
+        )
+        .unwrap();
+
+        registry.register(Box::new(authentications.clone())).unwrap();
+        registry.register(Box::new(userinfo_requests.clone())).unwrap();
+        registry.register(Box::new(session_age.clone())).unwrap();
+        registry.register(Box::new(password_verification_duration.clone())).unwrap();
+        registry.register(Box::new(configured_users.clone())).unwrap();
+        registry.register(Box::new(active_keys.clone())).unwrap();
+
+        Self {
+            registry,
+            authentications,
+            userinfo_requests,
+            session_age,
+            password_verification_duration,
+            configured_users,
+            active_keys,
+        }
+    }
+
+    pub fn render(&self) -> Result<String, prometheus::Error> {
+        TextEncoder::new().encode_to_string(&self.registry.gather())
+    }
+}
+
+async fn get_metrics(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    match metrics.render() {
+        Ok(body) => Ok(([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], body)),
+        Err(err) => {
+            log::error!("could not render metrics: {}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub fn metrics_router(metrics: Arc<Metrics>) -> Router {
+    Router::new().route("/metrics", get(get_metrics)).with_state(metrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        metrics.authentications.with_label_values(&["success"]).inc();
+        metrics.configured_users.with_label_values(&["default"]).set(3);
+        metrics.active_keys.set(1);
+        let actual = metrics.render().unwrap();
+        assert!(actual.contains("staticauth_authentications_total{result=\"success\"} 1"));
+        assert!(actual.contains("staticauth_configured_users{realm=\"default\"} 3"));
+        assert!(actual.contains("staticauth_active_keys 1"));
+    }
+}
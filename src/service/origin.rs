@@ -0,0 +1,186 @@
+use axum::http::header::{HOST, ORIGIN, REFERER};
+use axum::http::uri::Authority;
+use axum::http::HeaderMap;
+use thiserror::Error;
+use url::{Host, Origin, Url};
+
+const X_FORWARDED_HOST: &str = "X-Forwarded-Host";
+
+#[derive(Debug, PartialEq, Error)]
+pub enum OriginError {
+    #[error("neither origin nor referer header is present")]
+    Missing,
+    #[error("malformed {0} header")]
+    Malformed(&'static str),
+    #[error("origin '{0}' is not in the allowed origins")]
+    NotAllowed(String),
+    #[error("missing or malformed host header")]
+    InvalidHost,
+    #[error("origin '{origin}' does not match host '{host}'")]
+    HostMismatch { origin: String, host: String },
+}
+
+pub fn parse_origin(text: &str) -> Option<Origin> {
+    let origin = Url::parse(text).ok()?.origin();
+    origin.is_tuple().then_some(origin)
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn request_origin(headers: &HeaderMap) -> Result<Origin, OriginError> {
+    if let Some(origin) = headers.get(ORIGIN) {
+        let origin = origin.to_str().map_err(|_| OriginError::Malformed("origin"))?;
+        return parse_origin(origin).ok_or(OriginError::Malformed("origin"));
+    }
+    if let Some(referer) = headers.get(REFERER) {
+        let referer = referer.to_str().map_err(|_| OriginError::Malformed("referer"))?;
+        return parse_origin(referer).ok_or(OriginError::Malformed("referer"));
+    }
+    Err(OriginError::Missing)
+}
+
+pub fn request_host(headers: &HeaderMap, trust_forwarded: bool) -> Option<Authority> {
+    let forwarded = header_str(headers, X_FORWARDED_HOST)
+        .filter(|_| trust_forwarded)
+        .and_then(|v| v.split(',').next())
+        .map(str::trim);
+    let host = forwarded.or_else(|| header_str(headers, HOST.as_str()))?;
+    host.parse().ok()
+}
+
+fn default_port(scheme: &str) -> Option<u16> {
+    match scheme {
+        "http" | "ws" => Some(80),
+        "https" | "wss" => Some(443),
+        _ => None,
+    }
+}
+
+fn matches_host(origin: &Origin, host: &Authority) -> bool {
+    let Origin::Tuple(scheme, origin_host, origin_port) = origin else {
+        return false;
+    };
+    let Ok(host_name) = Host::parse(host.host()) else {
+        return false;
+    };
+    let host_port = host.port_u16().or(default_port(scheme));
+    *origin_host == host_name && host_port == Some(*origin_port)
+}
+
+pub fn check_origin(
+    headers: &HeaderMap,
+    trust_forwarded: bool,
+    allowed: &[Origin],
+) -> Result<(), OriginError> {
+    let origin = request_origin(headers)?;
+    if !allowed.is_empty() {
+        return match allowed.contains(&origin) {
+            true => Ok(()),
+            false => Err(OriginError::NotAllowed(origin.ascii_serialization())),
+        };
+    }
+    let host = request_host(headers, trust_forwarded).ok_or(OriginError::InvalidHost)?;
+    match matches_host(&origin, &host) {
+        true => Ok(()),
+        false => Err(OriginError::HostMismatch {
+            origin: origin.ascii_serialization(),
+            host: host.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (key, value) in pairs {
+            headers.append(*key, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_check_origin_same_host() {
+        let headers = headers(&[("Origin", "http://localhost:8080"), ("Host", "localhost:8080")]);
+        assert_eq!(Ok(()), check_origin(&headers, false, &[]));
+    }
+
+    #[test]
+    fn test_check_origin_default_port() {
+        let headers = headers(&[("Origin", "https://example.com"), ("Host", "example.com")]);
+        assert_eq!(Ok(()), check_origin(&headers, false, &[]));
+    }
+
+    #[test]
+    fn test_check_origin_port_mismatch() {
+        let headers = headers(&[("Origin", "https://example.com"), ("Host", "example.com:8080")]);
+        let actual = check_origin(&headers, false, &[]);
+        assert!(matches!(actual, Err(OriginError::HostMismatch { .. })));
+    }
+
+    #[test]
+    fn test_check_origin_explicit_port_without_host_port() {
+        let headers = headers(&[("Origin", "https://example.com:8443"), ("Host", "example.com")]);
+        let actual = check_origin(&headers, false, &[]);
+        assert!(matches!(actual, Err(OriginError::HostMismatch { .. })));
+    }
+
+    #[test]
+    fn test_check_origin_forwarded_host_trusted() {
+        let headers = headers(&[
+            ("Origin", "https://example.com"),
+            ("Host", "127.0.0.1:8080"),
+            ("X-Forwarded-Host", "example.com"),
+        ]);
+        assert_eq!(Ok(()), check_origin(&headers, true, &[]));
+    }
+
+    #[test]
+    fn test_check_origin_forwarded_host_untrusted() {
+        let headers = headers(&[
+            ("Origin", "https://example.com"),
+            ("Host", "127.0.0.1:8080"),
+            ("X-Forwarded-Host", "example.com"),
+        ]);
+        let actual = check_origin(&headers, false, &[]);
+        assert!(matches!(actual, Err(OriginError::HostMismatch { .. })));
+    }
+
+    #[test]
+    fn test_check_origin_referer_fallback() {
+        let headers =
+            headers(&[("Referer", "https://example.com/signin?rd=/"), ("Host", "example.com")]);
+        assert_eq!(Ok(()), check_origin(&headers, false, &[]));
+    }
+
+    #[test]
+    fn test_check_origin_missing() {
+        let headers = headers(&[("Host", "example.com")]);
+        assert_eq!(Err(OriginError::Missing), check_origin(&headers, false, &[]));
+    }
+
+    #[test]
+    fn test_check_origin_malformed() {
+        let headers = headers(&[("Origin", "null"), ("Host", "example.com")]);
+        assert_eq!(Err(OriginError::Malformed("origin")), check_origin(&headers, false, &[]));
+    }
+
+    #[test]
+    fn test_check_origin_allowed_origins() {
+        let allowed = [parse_origin("https://auth.example.com").unwrap()];
+        let headers = headers(&[("Origin", "https://auth.example.com:443"), ("Host", "localhost")]);
+        assert_eq!(Ok(()), check_origin(&headers, false, &allowed));
+    }
+
+    #[test]
+    fn test_check_origin_not_allowed() {
+        let allowed = [parse_origin("https://auth.example.com").unwrap()];
+        let headers = headers(&[("Origin", "https://evil.example.com"), ("Host", "localhost")]);
+        let expected = Err(OriginError::NotAllowed("https://evil.example.com".into()));
+        assert_eq!(expected, check_origin(&headers, false, &allowed));
+    }
+}
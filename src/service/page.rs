@@ -1,9 +1,28 @@
 use axum::response::Html;
 
 const SIGNIN_HTML_TEMPLATE: &str = include_str!("page/signin.html");
+const DEFAULT_TITLE: &str = "Sign In";
 
-pub fn get_signin_html() -> Html<&'static str> {
-    Html::from(SIGNIN_HTML_TEMPLATE)
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn get_signin_html(title: Option<&str>) -> Html<String> {
+    let html = SIGNIN_HTML_TEMPLATE
+        .replace("{{title}}", &escape_html(title.unwrap_or(DEFAULT_TITLE)))
+        .replace("{{heading}}", &escape_html(title.unwrap_or_default()));
+    Html::from(html)
 }
 
 #[cfg(test)]
@@ -12,7 +31,15 @@ mod tests {
 
     #[test]
     fn test_get_signin_html() {
-        let html = get_signin_html();
+        let html = get_signin_html(None);
         assert!(!html.0.is_empty());
+        assert!(html.0.contains("<title>Sign In</title>"));
+    }
+
+    #[test]
+    fn test_get_signin_html_title() {
+        let html = get_signin_html(Some("Family <Media>"));
+        assert!(html.0.contains("<title>Family &lt;Media&gt;</title>"));
+        assert!(!html.0.contains("{{"));
     }
 }
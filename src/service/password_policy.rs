@@ -0,0 +1,147 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+
+const MAX_STRENGTH: u8 = 4;
+const GENERATED_LENGTH: usize = 20;
+const GENERATED_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+#[derive(Debug, Error)]
+pub enum PolicyError {
+    #[error("password must be at least {0} characters long")]
+    TooShort(usize),
+    #[error("password is too weak (strength {0} of {MAX_STRENGTH}, at least {1} is required)")]
+    TooWeak(u8, u8),
+    #[error("password has been seen {0} times in data breaches")]
+    Breached(u64),
+    #[error("could not check breached passwords: {0}")]
+    BreachCheckFailed(#[from] io::Error),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub min_strength: u8,
+    pub breached_passwords_dir: Option<PathBuf>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self { min_length: 8, min_strength: 2, breached_passwords_dir: None }
+    }
+}
+
+impl PasswordPolicy {
+    pub fn check(&self, password: &str) -> Result<(), PolicyError> {
+        if password.chars().count() < self.min_length {
+            return Err(PolicyError::TooShort(self.min_length));
+        }
+        let strength = strength(password);
+        if strength < self.min_strength {
+            return Err(PolicyError::TooWeak(strength, self.min_strength));
+        }
+        if let Some(dir) = &self.breached_passwords_dir {
+            let count = breach_count(dir, password)?;
+            if count > 0 {
+                return Err(PolicyError::Breached(count));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Looks the password up in a local copy of the Have I Been Pwned range files. Each file is named
+// after the first five hex digits of a SHA-1 hash and lists the remaining digits with a count.
+fn breach_count(dir: &Path, password: &str) -> io::Result<u64> {
+    let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+    let (prefix, suffix) = hash.split_at(5);
+    let content = fs::read_to_string(dir.join(format!("{}.txt", prefix)))?;
+    let count = content.lines().find_map(|line| {
+        let (s, count) = line.trim().split_once(':')?;
+        s.eq_ignore_ascii_case(suffix).then(|| count.parse().unwrap_or(1))
+    });
+    Ok(count.unwrap_or(0))
+}
+
+// Strength from 0 to 4, as estimated by zxcvbn.
+pub fn strength(password: &str) -> u8 {
+    zxcvbn::zxcvbn(password, &[]).score().into()
+}
+
+// A random alphanumeric password with about 119 bits of entropy. Bytes past the largest multiple
+// of the alphabet size are discarded, so that every character is equally likely.
+pub fn generate_password() -> String {
+    let limit = 256 - 256 % GENERATED_ALPHABET.len();
+    let mut password = String::with_capacity(GENERATED_LENGTH);
+    while password.len() < GENERATED_LENGTH {
+        let mut byte = [0u8; 1];
+        OsRng.fill_bytes(&mut byte);
+        if (byte[0] as usize) < limit {
+            password.push(GENERATED_ALPHABET[byte[0] as usize % GENERATED_ALPHABET.len()] as char);
+        }
+    }
+    password
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strength() {
+        let passwords = [
+            "password",
+            "P@ssw0rd",
+            "abcdefgh",
+            "qwerty123",
+            "summer2024",
+            "tr0ub4dor&3",
+            "correcthorsebatterystaple",
+        ];
+        let expected = vec![0, 0, 0, 0, 1, 4, 4];
+        let actual: Vec<_> = passwords.iter().map(|p| strength(p)).collect();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_generate_password() {
+        let password = generate_password();
+        assert_eq!(GENERATED_LENGTH, password.len());
+        assert_eq!(MAX_STRENGTH, strength(&password));
+        assert_ne!(password, generate_password());
+    }
+
+    #[test]
+    fn test_check() {
+        let policy = PasswordPolicy::default();
+        assert!(matches!(policy.check(""), Err(PolicyError::TooShort(8))));
+        assert!(matches!(policy.check("password"), Err(PolicyError::TooWeak(0, 2))));
+        assert!(policy.check("correct horse battery staple").is_ok());
+    }
+
+    #[test]
+    fn test_check_breached() {
+        let dir = tempfile::tempdir().unwrap();
+        // SHA-1 of "correct horse battery staple" is ABF7AAD6438836DBE526AA231ABDE2D0EEF74D42.
+        let content = "0000000000000000000000000000000000A:1\r\n\
+                       AD6438836DBE526AA231ABDE2D0EEF74D42:42\r\n";
+        fs::write(dir.path().join("ABF7A.txt"), content).unwrap();
+        let policy = PasswordPolicy {
+            breached_passwords_dir: Some(dir.path().into()),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            policy.check("correct horse battery staple"),
+            Err(PolicyError::Breached(42))
+        ));
+        assert!(matches!(
+            policy.check("correcthorsebatterystaple"),
+            Err(PolicyError::BreachCheckFailed(_))
+        ));
+    }
+}
@@ -0,0 +1,223 @@
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+
+const MAX_ENTRIES: NonZeroUsize = match NonZeroUsize::new(100_000) {
+    Some(n) => n,
+    None => unreachable!(),
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+impl RateLimit {
+    fn refill_rate(&self) -> f64 {
+        self.per_minute as f64 / 60.0
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimiter<K: Eq + Hash> {
+    limit: RateLimit,
+    buckets: Arc<Mutex<LruCache<K, Bucket>>>,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub fn new(limit: RateLimit) -> Self {
+        Self::with_capacity(limit, MAX_ENTRIES)
+    }
+
+    fn with_capacity(limit: RateLimit, capacity: NonZeroUsize) -> Self {
+        Self { limit, buckets: Arc::new(Mutex::new(LruCache::new(capacity))) }
+    }
+
+    fn enabled(&self) -> bool {
+        self.limit.burst > 0
+    }
+
+    fn refill(&self, bucket: &mut Bucket, now: Instant) {
+        let elapsed = now.saturating_duration_since(bucket.updated_at).as_secs_f64();
+        let tokens = bucket.tokens + elapsed * self.limit.refill_rate();
+        bucket.tokens = tokens.min(self.limit.burst as f64);
+        bucket.updated_at = now;
+    }
+
+    pub fn check(&self, key: K, now: Option<Instant>) -> Result<(), Duration> {
+        self.acquire(key, now, true)
+    }
+
+    // Like `check`, but leaves the bucket untouched.
+    fn peek(&self, key: K, now: Option<Instant>) -> Result<(), Duration> {
+        self.acquire(key, now, false)
+    }
+
+    fn acquire(&self, key: K, now: Option<Instant>, consume: bool) -> Result<(), Duration> {
+        if !self.enabled() {
+            return Ok(());
+        }
+        let now = now.unwrap_or_else(Instant::now);
+        let mut buckets = self.buckets.lock().unwrap();
+        // Full buckets are equivalent to missing ones, so evicting the least
+        // recently used entry never tightens anyone's limit.
+        let bucket = buckets
+            .get_or_insert_mut(key, || Bucket { tokens: self.limit.burst as f64, updated_at: now });
+        self.refill(bucket, now);
+        if bucket.tokens >= 1.0 {
+            if consume {
+                bucket.tokens -= 1.0;
+            }
+            return Ok(());
+        }
+        let rate = self.limit.refill_rate();
+        if rate == 0.0 {
+            return Err(Duration::MAX);
+        }
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoginRateLimits {
+    by_address: RateLimiter<IpAddr>,
+    by_username: RateLimiter<(String, String)>,
+}
+
+impl LoginRateLimits {
+    pub fn new(by_address: RateLimit, by_username: RateLimit) -> Self {
+        Self {
+            by_address: RateLimiter::new(by_address),
+            by_username: RateLimiter::new(by_username),
+        }
+    }
+
+    pub fn check(
+        &self,
+        address: Option<IpAddr>,
+        realm: &str,
+        username: &str,
+        now: Option<Instant>,
+    ) -> Result<(), Duration> {
+        let now = now.unwrap_or_else(Instant::now);
+        if let Some(address) = address {
+            self.by_address.peek(address, Some(now))?;
+        }
+        if self.by_username.enabled() {
+            self.by_username.check((realm.into(), username.into()), Some(now))?;
+        }
+        if let Some(address) = address {
+            self.by_address.check(address, Some(now))?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for LoginRateLimits {
+    fn default() -> Self {
+        let disabled = RateLimit { burst: 0, per_minute: 0 };
+        Self::new(disabled, disabled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_burst() {
+        let limiter = RateLimiter::new(RateLimit { burst: 3, per_minute: 60 });
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(Ok(()), limiter.check("user", Some(now)));
+        }
+        assert_eq!(Err(Duration::from_secs(1)), limiter.check("user", Some(now)));
+        assert_eq!(Ok(()), limiter.check("other", Some(now)));
+    }
+
+    #[test]
+    fn test_check_refill() {
+        let limiter = RateLimiter::new(RateLimit { burst: 2, per_minute: 6 });
+        let now = Instant::now();
+        assert_eq!(Ok(()), limiter.check("user", Some(now)));
+        assert_eq!(Ok(()), limiter.check("user", Some(now)));
+        assert_eq!(Err(Duration::from_secs(10)), limiter.check("user", Some(now)));
+
+        let later = now + Duration::from_secs(5);
+        assert_eq!(Err(Duration::from_secs(5)), limiter.check("user", Some(later)));
+
+        let later = now + Duration::from_secs(10);
+        assert_eq!(Ok(()), limiter.check("user", Some(later)));
+        assert!(limiter.check("user", Some(later)).is_err());
+
+        let later = now + Duration::from_secs(3600);
+        assert_eq!(Ok(()), limiter.check("user", Some(later)));
+        assert_eq!(Ok(()), limiter.check("user", Some(later)));
+        assert!(limiter.check("user", Some(later)).is_err());
+    }
+
+    #[test]
+    fn test_check_disabled() {
+        let limiter = RateLimiter::new(RateLimit { burst: 0, per_minute: 0 });
+        for _ in 0..100 {
+            assert_eq!(Ok(()), limiter.check("user", None));
+        }
+    }
+
+    #[test]
+    fn test_check_evicts_least_recently_used() {
+        let limit = RateLimit { burst: 1, per_minute: 1 };
+        let limiter = RateLimiter::with_capacity(limit, NonZeroUsize::new(2).unwrap());
+        let now = Some(Instant::now());
+        assert_eq!(Ok(()), limiter.check("alice", now));
+        assert_eq!(Ok(()), limiter.check("bob", now));
+        assert!(limiter.check("alice", now).is_err());
+        assert_eq!(Ok(()), limiter.check("carol", now));
+        assert_eq!(2, limiter.buckets.lock().unwrap().len());
+        assert!(limiter.check("alice", now).is_err());
+        assert_eq!(Ok(()), limiter.check("bob", now));
+    }
+
+    #[test]
+    fn test_login_rate_limits() {
+        let limits = LoginRateLimits::new(
+            RateLimit { burst: 3, per_minute: 1 },
+            RateLimit { burst: 1, per_minute: 1 },
+        );
+        let address = Some("192.0.2.1".parse().unwrap());
+        let now = Some(Instant::now());
+        assert_eq!(Ok(()), limits.check(address, "default", "alice", now));
+        assert!(limits.check(address, "default", "alice", now).is_err());
+        assert!(limits.check(address, "default", "alice", now).is_err());
+        assert_eq!(Ok(()), limits.check(address, "work", "alice", now));
+        assert_eq!(Ok(()), limits.check(None, "default", "bob", now));
+        assert_eq!(Ok(()), limits.check(address, "default", "carol", now));
+        assert_eq!(Err(Duration::from_secs(60)), limits.check(address, "default", "dave", now));
+        assert!(limits.check(address, "default", "dave", now).is_err());
+        assert_eq!(Ok(()), limits.check(None, "default", "dave", now));
+    }
+
+    #[test]
+    fn test_login_rate_limits_username_disabled() {
+        let limits = LoginRateLimits::new(
+            RateLimit { burst: 2, per_minute: 1 },
+            RateLimit { burst: 0, per_minute: 0 },
+        );
+        let now = Some(Instant::now());
+        for _ in 0..10 {
+            assert_eq!(Ok(()), limits.check(None, "default", "alice", now));
+        }
+        assert!(limits.by_username.buckets.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::redirection::RedirectDomain;
+
+#[derive(Debug, Clone)]
+pub struct Realm {
+    pub name: String,
+    pub hosts: Vec<String>,
+    pub path_prefix: Option<String>,
+    pub cookie_name: String,
+    pub session_absolute_timeout: Duration,
+    pub title: Option<String>,
+    pub allowed_redirect_domains: Vec<RedirectDomain>,
+    pub client_certificate_auth: bool,
+    pub users: HashMap<String, String>,
+}
+
+impl Realm {
+    fn matches_host(&self, host: Option<&str>) -> bool {
+        if self.hosts.is_empty() {
+            return true;
+        }
+        host.is_some_and(|host| self.hosts.iter().any(|h| h.eq_ignore_ascii_case(host)))
+    }
+
+    fn matches_path(&self, path: &str) -> bool {
+        let Some(prefix) = &self.path_prefix else {
+            return true;
+        };
+        match path.strip_prefix(prefix.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        }
+    }
+
+    pub fn matches(&self, host: Option<&str>, path: &str) -> bool {
+        self.matches_host(host) && self.matches_path(path)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RealmTable {
+    realms: Vec<Arc<Realm>>,
+    default: Arc<Realm>,
+}
+
+impl RealmTable {
+    pub fn new(realms: Vec<Realm>, default: Realm) -> Self {
+        Self { realms: realms.into_iter().map(Arc::new).collect(), default: Arc::new(default) }
+    }
+
+    pub fn select(&self, host: Option<&str>, path: &str) -> Arc<Realm> {
+        let realm = self.realms.iter().find(|r| r.matches(host, path));
+        realm.unwrap_or(&self.default).clone()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Realm>> {
+        self.realms.iter().chain([&self.default])
+    }
+
+    pub fn path_prefixes(&self) -> Vec<&str> {
+        let mut prefixes: Vec<&str> =
+            self.realms.iter().filter_map(|r| r.path_prefix.as_deref()).collect();
+        prefixes.sort_unstable();
+        prefixes.dedup();
+        prefixes
+    }
+
+    // Realms sharing the user's old hash are updated together, as they were configured from
+    // the same entry.
+    pub fn replace_password(&self, username: &str, old: &str, new: &str) -> Self {
+        let replace = |realm: &Arc<Realm>| {
+            if realm.users.get(username).map(String::as_str) != Some(old) {
+                return realm.clone();
+            }
+            let mut realm = Realm::clone(realm);
+            realm.users.insert(username.into(), new.into());
+            Arc::new(realm)
+        };
+        Self { realms: self.realms.iter().map(replace).collect(), default: replace(&self.default) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn realm(name: &str, hosts: &[&str], path_prefix: Option<&str>) -> Realm {
+        Realm {
+            name: name.into(),
+            hosts: hosts.iter().map(|h| h.to_string()).collect(),
+            path_prefix: path_prefix.map(|p| p.into()),
+            cookie_name: format!("session_{}", name),
+            session_absolute_timeout: Duration::from_secs(60),
+            title: None,
+            allowed_redirect_domains: vec![],
+            client_certificate_auth: true,
+            users: HashMap::new(),
+        }
+    }
+
+    fn table() -> RealmTable {
+        let realms = vec![
+            realm("media", &["media.example.com"], None),
+            realm("work", &[], Some("/work")),
+            realm("both", &["both.example.com"], Some("/both")),
+        ];
+        RealmTable::new(realms, realm("default", &[], None))
+    }
+
+    #[test]
+    fn test_select_by_host() {
+        let actual = table().select(Some("MEDIA.example.com"), "/signin");
+        assert_eq!("media", actual.name);
+    }
+
+    #[test]
+    fn test_select_by_path_prefix() {
+        let table = table();
+        assert_eq!("work", table.select(Some("other.example.com"), "/work/signin").name);
+        assert_eq!("work", table.select(None, "/work").name);
+        assert_eq!("default", table.select(None, "/workshop/signin").name);
+    }
+
+    #[test]
+    fn test_select_by_host_and_path_prefix() {
+        let table = table();
+        assert_eq!("both", table.select(Some("both.example.com"), "/both/signin").name);
+        assert_eq!("default", table.select(Some("both.example.com"), "/signin").name);
+        assert_eq!("default", table.select(Some("example.com"), "/both/signin").name);
+    }
+
+    #[test]
+    fn test_select_default() {
+        let actual = table().select(None, "/signin");
+        assert_eq!("default", actual.name);
+    }
+
+    #[test]
+    fn test_path_prefixes() {
+        let expected = vec!["/both", "/work"];
+        assert_eq!(expected, table().path_prefixes());
+    }
+
+    #[test]
+    fn test_replace_password() {
+        let mut media = realm("media", &["media.example.com"], None);
+        media.users.insert("alice".into(), "old".into());
+        let mut work = realm("work", &[], Some("/work"));
+        work.users.insert("alice".into(), "other".into());
+        let mut default = realm("default", &[], None);
+        default.users.insert("alice".into(), "old".into());
+        let table = RealmTable::new(vec![media, work], default);
+
+        let actual = table.replace_password("alice", "old", "new");
+        let passwords: Vec<_> = actual.iter().map(|r| r.users["alice"].as_str()).collect();
+        assert_eq!(vec!["new", "other", "new"], passwords);
+        let passwords: Vec<_> = table.iter().map(|r| r.users["alice"].as_str()).collect();
+        assert_eq!(vec!["old", "other", "old"], passwords);
+    }
+}
@@ -1,10 +1,86 @@
-use url::Url;
+use std::str::FromStr;
 
-pub fn normalize_path(base: &str, path: &str) -> Option<String> {
+use thiserror::Error;
+use url::{Host, Url};
+
+#[derive(Debug, PartialEq, Error)]
+#[error("invalid redirect domain '{0}'")]
+pub struct InvalidRedirectDomain(String);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedirectDomain {
+    scheme: Option<String>,
+    host: String,
+    wildcard: bool,
+    port: Option<u16>,
+}
+
+impl FromStr for RedirectDomain {
+    type Err = InvalidRedirectDomain;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidRedirectDomain(s.into());
+        let (scheme, rest) = match s.split_once("://") {
+            Some((scheme @ ("http" | "https"), rest)) => (Some(scheme.to_owned()), rest),
+            Some(_) => return Err(invalid()),
+            None => (None, s),
+        };
+        let (wildcard, rest) = match rest.strip_prefix("*.") {
+            Some(rest) => (true, rest),
+            None => (false, rest),
+        };
+        let url = Url::parse(&format!("http://{}", rest)).map_err(|_| invalid())?;
+        if url.path() != "/" || url.query().is_some() || !url.username().is_empty() {
+            return Err(invalid());
+        }
+        let host = match url.host() {
+            Some(Host::Domain(d)) => d.to_owned(),
+            Some(host) if !wildcard => host.to_string(),
+            _ => return Err(invalid()),
+        };
+        Ok(Self { scheme, host, wildcard, port: explicit_port(&url, rest) })
+    }
+}
+
+// Urls drop the default port of their scheme, so a port of 80 is only kept when the domain is
+// parsed as https.
+fn explicit_port(url: &Url, authority: &str) -> Option<u16> {
+    url.port().or_else(|| Url::parse(&format!("https://{}", authority)).ok()?.port())
+}
+
+impl RedirectDomain {
+    pub fn matches(&self, url: &Url) -> bool {
+        let scheme_ok = match &self.scheme {
+            Some(scheme) => url.scheme() == scheme,
+            None => matches!(url.scheme(), "http" | "https"),
+        };
+        let host_ok = match (url.host(), self.wildcard) {
+            (Some(Host::Domain(d)), true) => {
+                d.strip_suffix(&self.host).is_some_and(|sub| sub.len() > 1 && sub.ends_with('.'))
+            }
+            (Some(host), false) => host.to_string() == self.host,
+            _ => false,
+        };
+        let port_ok = match self.port {
+            Some(port) => url.port_or_known_default() == Some(port),
+            None => url.port().is_none(),
+        };
+        scheme_ok && host_ok && port_ok
+    }
+}
+
+pub fn normalize_path(base: &str, path: &str, allowed: &[RedirectDomain]) -> Option<String> {
     let host = Url::parse("http://localhost").unwrap();
     let full_base = host.join(base).ok()?;
     let full_path = full_base.join(path).ok()?;
-    host.make_relative(&full_path).map(|r| format!("/{}", r))
+    if let Some(r) = host.make_relative(&full_path) {
+        return Some(format!("/{}", r));
+    }
+    let url = Url::parse(path).ok()?;
+    if !url.username().is_empty() || url.password().is_some() {
+        return None;
+    }
+    allowed.iter().any(|d| d.matches(&url)).then(|| url.to_string())
 }
 
 pub fn add_query_to_path(path: &str, key: &str, value: &str) -> Option<String> {
@@ -23,7 +99,7 @@ mod tests {
         let base = "/a/b/c";
         let path = "d";
         let expected = Some("/a/b/d".into());
-        let actual = normalize_path(base, path);
+        let actual = normalize_path(base, path, &[]);
         assert_eq!(expected, actual);
     }
 
@@ -32,7 +108,7 @@ mod tests {
         let base = "/a/b/c/";
         let path = "d";
         let expected = Some("/a/b/c/d".into());
-        let actual = normalize_path(base, path);
+        let actual = normalize_path(base, path, &[]);
         assert_eq!(expected, actual);
     }
 
@@ -41,7 +117,7 @@ mod tests {
         let base = "/a/b/c";
         let path = "/d";
         let expected = Some("/d".into());
-        let actual = normalize_path(base, path);
+        let actual = normalize_path(base, path, &[]);
         assert_eq!(expected, actual);
     }
 
@@ -50,7 +126,7 @@ mod tests {
         let base = "/a/b/c";
         let path = "../d";
         let expected = Some("/a/d".into());
-        let actual = normalize_path(base, path);
+        let actual = normalize_path(base, path, &[]);
         assert_eq!(expected, actual);
     }
 
@@ -59,10 +135,95 @@ mod tests {
         let base = "/a/b/c";
         let path = "https://example.com/x/y/z";
         let expected = None;
-        let actual = normalize_path(base, path);
+        let actual = normalize_path(base, path, &[]);
+        assert_eq!(expected, actual);
+    }
+
+    fn domains(domains: &[&str]) -> Vec<RedirectDomain> {
+        domains.iter().map(|d| d.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_normalize_path_allowed_domain() {
+        let allowed = domains(&["app1.example.com"]);
+        let path = "https://app1.example.com/x?y=z";
+        let expected = Some("https://app1.example.com/x?y=z".into());
+        let actual = normalize_path("/a/b/c", path, &allowed);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_normalize_path_allowed_wildcard_domain() {
+        let allowed = domains(&["*.example.com"]);
+        let expected = Some("https://a.b.example.com/".into());
+        let actual = normalize_path("/a/b/c", "https://a.b.example.com", &allowed);
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_normalize_path_wildcard_excludes_parent_domain() {
+        let allowed = domains(&["*.example.com"]);
+        assert_eq!(None, normalize_path("/a/b/c", "https://example.com/", &allowed));
+        assert_eq!(None, normalize_path("/a/b/c", "https://badexample.com/", &allowed));
+    }
+
+    #[test]
+    fn test_normalize_path_scheme_restriction() {
+        let allowed = domains(&["https://app1.example.com"]);
+        assert_eq!(None, normalize_path("/a/b/c", "http://app1.example.com/", &allowed));
+        assert_eq!(None, normalize_path("/a/b/c", "javascript://app1.example.com/", &allowed));
+    }
+
+    #[test]
+    fn test_normalize_path_port_restriction() {
+        let allowed = domains(&["app1.example.com:8443"]);
+        let expected = Some("https://app1.example.com:8443/".into());
+        let actual = normalize_path("/a/b/c", "https://app1.example.com:8443/", &allowed);
+        assert_eq!(expected, actual);
+        assert_eq!(None, normalize_path("/a/b/c", "https://app1.example.com/", &allowed));
+    }
+
+    #[test]
+    fn test_normalize_path_explicit_default_port() {
+        let allowed = domains(&["https://app1.example.com:443", "http://app2.example.com:80"]);
+        let expected = Some("https://app1.example.com/".into());
+        assert_eq!(expected, normalize_path("/a/b/c", "https://app1.example.com/", &allowed));
+        let actual = normalize_path("/a/b/c", "https://app1.example.com:443/", &allowed);
+        assert_eq!(expected, actual);
+        let expected = Some("http://app2.example.com/".into());
+        assert_eq!(expected, normalize_path("/a/b/c", "http://app2.example.com/", &allowed));
+
+        let allowed = domains(&["app1.example.com:80"]);
+        let expected = Some("http://app1.example.com/".into());
+        assert_eq!(expected, normalize_path("/a/b/c", "http://app1.example.com/", &allowed));
+        assert_eq!(None, normalize_path("/a/b/c", "https://app1.example.com/", &allowed));
+
+        let allowed = domains(&["app1.example.com"]);
+        let expected = Some("https://app1.example.com/".into());
+        let actual = normalize_path("/a/b/c", "https://app1.example.com:443/", &allowed);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_normalize_path_rejects_credentials() {
+        let allowed = domains(&["app1.example.com"]);
+        let path = "https://user@app1.example.com/";
+        assert_eq!(None, normalize_path("/a/b/c", path, &allowed));
+    }
+
+    #[test]
+    fn test_normalize_path_rejects_protocol_relative() {
+        let allowed = domains(&["app1.example.com"]);
+        assert_eq!(None, normalize_path("/a/b/c", "//app1.example.com/x", &allowed));
+    }
+
+    #[test]
+    fn test_redirect_domain_invalid() {
+        assert!("ftp://example.com".parse::<RedirectDomain>().is_err());
+        assert!("example.com/path".parse::<RedirectDomain>().is_err());
+        assert!("*.127.0.0.1".parse::<RedirectDomain>().is_err());
+    }
+
     #[test]
     fn test_add_query_to_path_basic() {
         let path = "/a/b/c";
@@ -1,55 +1,268 @@
 use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
 use std::time::Duration;
 
-use super::auth::verify_password;
+use super::access_log::{
+    access_log, with_request_id, with_request_id_blocking, RequestError, RequestUser,
+};
+use super::audit::{AuditEvent, AuditEventKind, AuditLog};
+use super::auth::Verifier;
+use super::client::{ClientAddress, TrustedProxies};
+use super::connection::ConnectionInfo;
+use super::handoff::{HandoffGrant, HandoffStore};
+use super::hashing::{HashingError, HashingPool};
 use super::headers::{X_AUTH_REQUEST_REDIRECT, X_AUTH_REQUEST_USER};
+use super::health::{healthz, readyz, Draining};
+use super::lockout::LockoutStore;
+use super::metrics::Metrics;
+use super::origin::{check_origin, request_host};
 use super::page::get_signin_html;
+use super::rate_limit::LoginRateLimits;
+use super::realm::{Realm, RealmTable};
 use super::redirection::{add_query_to_path, normalize_path};
 use super::session::{Session, ValidationOptions};
+use super::telemetry::trace_request;
+use super::user_store::UserStore;
 
-use axum::extract::{FromRef, Query, State};
-use axum::headers::{Host, Origin};
-use axum::http::{HeaderMap, StatusCode, Uri};
+use arc_swap::ArcSwap;
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts, OriginalUri, Query, State};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, Request, StatusCode};
+use axum::middleware::{self, Next};
 use axum::response::Result as AxumResult;
-use axum::response::{IntoResponse, Redirect};
+use axum::response::{IntoResponse, Redirect, Response};
 use axum::routing::{get, post};
-use axum::{Json, Router, TypedHeader};
-use axum_extra::extract::cookie::{Cookie, Key, SignedCookieJar};
+use axum::{Extension, Json, Router};
+use axum_extra::extract::cookie::{Cookie, Key, SameSite, SignedCookieJar};
 use chrono::Utc;
 use serde::Deserialize;
 use serde_json::json;
-
-const SESSION_COOKIE_NAME: &str = "session";
+use thiserror::Error;
+use tracing::Instrument;
+use url::{Origin, Url};
 
 #[derive(Debug, Clone)]
 pub struct ServiceConfig {
-    pub session_absolute_timeout: Duration,
     pub session_secret_key: Vec<u8>,
-    pub users: HashMap<String, String>,
+    pub trusted_proxies: TrustedProxies,
+    pub allowed_origins: Vec<Origin>,
+    pub sso_url: Option<Url>,
+    pub handoff_codes: HandoffStore,
+    pub realms: RealmTable,
+    pub metrics: Arc<Metrics>,
+    pub audit: Arc<AuditLog>,
+    pub draining: Draining,
+    pub rate_limits: LoginRateLimits,
+    pub lockout: LockoutStore,
+    pub hashing: HashingPool,
+    pub verifier: Verifier,
+    pub user_store: Option<Arc<UserStore>>,
+}
+
+#[derive(Debug, Error)]
+pub enum ServiceError {
+    #[error("invalid session secret key: {0}")]
+    InvalidKey(String),
+}
+
+struct Snapshot {
+    config: Arc<ServiceConfig>,
+    key: Key,
 }
 
-impl FromRef<ServiceConfig> for Key {
-    fn from_ref(config: &ServiceConfig) -> Self {
-        let key: &[u8] = &config.session_secret_key;
-        key.try_into().expect("invalid session secret key")
+impl Snapshot {
+    fn new(config: ServiceConfig) -> Result<Self, ServiceError> {
+        let key = Key::try_from(config.session_secret_key.as_slice())
+            .map_err(|err| ServiceError::InvalidKey(err.to_string()))?;
+        Ok(Self { config: Arc::new(config), key })
     }
 }
 
-impl ServiceConfig {
-    pub fn build(self) -> Router {
-        Router::new()
-            .route("/", get(|| async { Redirect::permanent("./signin") }))
-            .route("/signin", get(signin))
-            .route("/signout", get(signout))
-            .route("/authenticate", post(authenticate))
-            .route("/userinfo", get(userinfo))
+impl fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Snapshot").field("config", &self.config).finish_non_exhaustive()
+    }
+}
+
+// Router state. Requests share an immutable snapshot of the configuration, which can be
+// replaced as a whole without disturbing requests in flight.
+#[derive(Debug, Clone)]
+pub struct ServiceState(Arc<ArcSwap<Snapshot>>);
+
+impl ServiceState {
+    pub fn new(config: ServiceConfig) -> Result<Self, ServiceError> {
+        Ok(Self(Arc::new(ArcSwap::from_pointee(Snapshot::new(config)?))))
+    }
+
+    pub fn config(&self) -> Arc<ServiceConfig> {
+        self.0.load().config.clone()
+    }
+
+    pub fn replace(&self, config: ServiceConfig) -> Result<(), ServiceError> {
+        self.0.store(Arc::new(Snapshot::new(config)?));
+        self.record_config_metrics();
+        Ok(())
+    }
+
+    // Keeps the current configuration if the updated one is invalid.
+    pub fn update(&self, f: impl Fn(&ServiceConfig) -> ServiceConfig) -> Result<(), ServiceError> {
+        let mut result = Ok(());
+        self.0.rcu(|snapshot| match Snapshot::new(f(&snapshot.config)) {
+            Ok(updated) => {
+                result = Ok(());
+                Arc::new(updated)
+            }
+            Err(err) => {
+                result = Err(err);
+                snapshot.clone()
+            }
+        });
+        if result.is_ok() {
+            self.record_config_metrics();
+        }
+        result
+    }
+
+    // Realms that no longer exist are dropped from the gauge.
+    fn record_config_metrics(&self) {
+        let config = self.config();
+        config.metrics.configured_users.reset();
+        for realm in config.realms.iter() {
+            let users = realm.users.len() as i64;
+            config.metrics.configured_users.with_label_values(&[&realm.name]).set(users);
+        }
+        config.metrics.active_keys.set(1);
+    }
+
+    // Path prefixes are routed when the router is built, so replacing the configuration
+    // cannot add or remove realms mounted under a path prefix.
+    pub fn build(&self) -> Router {
+        self.record_config_metrics();
+        let config = self.config();
+
+        let mut router =
+            routes(&config).route("/healthz", get(healthz)).route("/readyz", get(readyz));
+        for prefix in config.realms.path_prefixes() {
+            router = router.nest(prefix, routes(&config));
+        }
+        let access_log = middleware::from_fn_with_state(self.clone(), access_log);
+        let load_snapshot = middleware::from_fn_with_state(self.clone(), load_snapshot);
+        router
             .fallback(|| async { (StatusCode::NOT_FOUND, "not found") })
-            .with_state(self)
+            .layer(access_log)
+            .layer(load_snapshot)
+            .with_state(self.clone())
+    }
+
+    fn snapshot(&self, parts: &Parts) -> Arc<Snapshot> {
+        match parts.extensions.get::<Arc<Snapshot>>() {
+            Some(snapshot) => snapshot.clone(),
+            None => self.0.load_full(),
+        }
+    }
+}
+
+// Loads the configuration once per request, so that every extractor of the request sees the
+// same snapshot even if it is replaced in the meantime.
+async fn load_snapshot<B>(
+    State(state): State<ServiceState>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let snapshot = state.0.load_full();
+    req.extensions_mut().insert(snapshot.config.trusted_proxies.clone());
+    req.extensions_mut().insert(snapshot);
+    next.run(req).await
+}
+
+pub struct CurrentConfig(pub Arc<ServiceConfig>);
+
+#[async_trait]
+impl FromRequestParts<ServiceState> for CurrentConfig {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ServiceState,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(CurrentConfig(state.snapshot(parts).config.clone()))
+    }
+}
+
+struct SessionJar(SignedCookieJar);
+
+#[async_trait]
+impl FromRequestParts<ServiceState> for SessionJar {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ServiceState,
+    ) -> Result<Self, Self::Rejection> {
+        let key = state.snapshot(parts).key.clone();
+        Ok(SessionJar(SignedCookieJar::from_headers(&parts.headers, key)))
+    }
+}
+
+fn routes(config: &ServiceConfig) -> Router<ServiceState> {
+    let record = middleware::from_fn_with_state(config.metrics.clone(), record_authentication);
+    let trace = middleware::from_fn(trace_request);
+    Router::new()
+        .route("/", get(|| async { Redirect::permanent("./signin") }))
+        .route("/signin", get(signin))
+        .route("/signout", get(signout))
+        .route("/authenticate", post(authenticate).layer(record))
+        .route("/userinfo", get(userinfo))
+        .route("/handoff", get(handoff))
+        .route("/handoff/callback", get(handoff_callback))
+        .route_layer(trace)
+}
+
+impl ServiceConfig {
+    pub fn build(self) -> Result<Router, ServiceError> {
+        Ok(ServiceState::new(self)?.build())
     }
 
     pub fn generate_key() -> Vec<u8> {
         Key::generate().master().into()
     }
+
+    // A configuration with a random key and a single realm of the given users, shared by the
+    // tests and benchmarks.
+    #[doc(hidden)]
+    pub fn for_tests(users: HashMap<String, String>) -> Self {
+        let realm = Realm {
+            name: "default".into(),
+            hosts: vec![],
+            path_prefix: None,
+            cookie_name: "session".into(),
+            session_absolute_timeout: Duration::from_secs(3600),
+            title: None,
+            allowed_redirect_domains: vec![],
+            client_certificate_auth: true,
+            users,
+        };
+        ServiceConfig {
+            session_secret_key: Self::generate_key(),
+            trusted_proxies: Default::default(),
+            allowed_origins: vec![],
+            sso_url: None,
+            handoff_codes: HandoffStore::new(Duration::from_secs(30)),
+            realms: RealmTable::new(vec![], realm),
+            metrics: Arc::new(Metrics::new()),
+            audit: Default::default(),
+            draining: Default::default(),
+            rate_limits: Default::default(),
+            lockout: LockoutStore::disabled(),
+            hashing: Default::default(),
+            verifier: Default::default(),
+            user_store: None,
+        }
+    }
 }
 
 enum JsonError {
@@ -57,46 +270,234 @@ enum JsonError {
     InvalidOrigin,
     InvalidRedirect,
     Unauthenticated,
+    RateLimited(Duration),
+    AccountLocked,
+    ServerBusy,
     InternalError,
 }
 
+impl JsonError {
+    fn kind(&self) -> &'static str {
+        use JsonError::*;
+        match self {
+            InvalidCredential => "invalid_credential",
+            InvalidOrigin => "invalid_origin",
+            InvalidRedirect => "invalid_redirect",
+            Unauthenticated => "unauthenticated",
+            RateLimited(_) => "rate_limited",
+            AccountLocked => "account_locked",
+            ServerBusy => "server_busy",
+            InternalError => "internal_error",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        use JsonError::*;
+        match self {
+            InvalidCredential | InvalidOrigin | InvalidRedirect => StatusCode::BAD_REQUEST,
+            Unauthenticated => StatusCode::UNAUTHORIZED,
+            RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AccountLocked => StatusCode::FORBIDDEN,
+            ServerBusy => StatusCode::SERVICE_UNAVAILABLE,
+            InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            JsonError::RateLimited(retry_after) => Some(*retry_after),
+            JsonError::ServerBusy => Some(Duration::from_secs(1)),
+            _ => None,
+        }
+    }
+}
+
 impl IntoResponse for JsonError {
     fn into_response(self) -> axum::response::Response {
-        use JsonError::*;
-        let resp = match self {
-            InvalidCredential => {
-                (StatusCode::BAD_REQUEST, Json::from(json!({"error": "invalid_credential"})))
-            }
-            InvalidOrigin => {
-                (StatusCode::BAD_REQUEST, Json::from(json!({"error": "invalid_origin"})))
-            }
-            InvalidRedirect => {
-                (StatusCode::BAD_REQUEST, Json::from(json!({"error": "invalid_redirect"})))
-            }
-            Unauthenticated => {
-                (StatusCode::UNAUTHORIZED, Json::from(json!({"error": "unauthenticated"})))
-            }
-            InternalError => {
-                (StatusCode::INTERNAL_SERVER_ERROR, Json::from(json!({"error": "internal_error"})))
-            }
+        let kind = self.kind();
+        let mut resp = (self.status(), Json::from(json!({"error": kind}))).into_response();
+        resp.extensions_mut().insert(RequestError(kind));
+        if let Some(retry_after) = self.retry_after() {
+            let secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            resp.headers_mut().insert(header::RETRY_AFTER, secs.into());
+        }
+        resp
+    }
+}
+
+async fn record_authentication<B>(
+    State(metrics): State<Arc<Metrics>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let resp = next.run(req).await;
+    let result = match resp.extensions().get::<RequestError>() {
+        Some(RequestError(kind)) => kind,
+        None if resp.status().is_success() => "success",
+        None => "invalid_request",
+    };
+    metrics.authentications.with_label_values(&[result]).inc();
+    resp
+}
+
+fn trusts_forwarded(
+    config: &ServiceConfig,
+    connection: &Option<ConnectInfo<ConnectionInfo>>,
+) -> bool {
+    let peer = connection.as_ref().and_then(|ConnectInfo(info)| info.peer_address);
+    config.trusted_proxies.is_trusted(peer.map(|a| a.ip()))
+}
+
+struct CurrentRealm(Arc<Realm>);
+
+#[async_trait]
+impl FromRequestParts<ServiceState> for CurrentRealm {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ServiceState,
+    ) -> Result<Self, Self::Rejection> {
+        let config = state.snapshot(parts).config.clone();
+        let connection = parts.extensions.get::<ConnectInfo<ConnectionInfo>>().cloned();
+        let host = request_host(&parts.headers, trusts_forwarded(&config, &connection));
+        let path = match parts.extensions.get::<OriginalUri>() {
+            Some(OriginalUri(uri)) => uri.path(),
+            None => parts.uri.path(),
         };
-        resp.into_response()
+        Ok(CurrentRealm(config.realms.select(host.as_ref().map(|h| h.host()), path)))
+    }
+}
+
+fn session_cookie(realm: &Realm, session: &Session, sso_enabled: bool) -> Cookie<'static> {
+    let mut cookie = session.to_cookie(&realm.cookie_name).into_owned();
+    // The handoff endpoint is reached by a cross-site redirect, which strict cookies do not follow.
+    if sso_enabled {
+        cookie.set_same_site(SameSite::Lax);
     }
+    cookie
+}
+
+enum SessionCheck {
+    Missing,
+    Valid(Session),
+    Expired(Session),
+    Revoked(Session),
 }
 
-fn check_origin(origin: &Origin, host: &Host) -> bool {
-    origin.hostname() == host.hostname() && origin.port() == host.port()
+fn check_session(realm: &Realm, jar: &SignedCookieJar) -> SessionCheck {
+    let options = ValidationOptions { now: None, absolute_timeout: realm.session_absolute_timeout };
+    let Some(cookie) = jar.get(&realm.cookie_name) else {
+        return SessionCheck::Missing;
+    };
+    let session = Session::from_cookie(cookie);
+    if !session.is_valid(options) {
+        SessionCheck::Expired(session)
+    } else if !realm.users.contains_key(&session.subject) {
+        SessionCheck::Revoked(session)
+    } else {
+        SessionCheck::Valid(session)
+    }
+}
+
+fn valid_session(realm: &Realm, jar: &SignedCookieJar) -> Option<Session> {
+    match check_session(realm, jar) {
+        SessionCheck::Valid(session) => Some(session),
+        _ => None,
+    }
+}
+
+fn user_agent(headers: &HeaderMap) -> Option<&str> {
+    headers.get(header::USER_AGENT).and_then(|value| value.to_str().ok())
+}
+
+fn removal_cookie(realm: &Realm) -> Cookie<'static> {
+    let mut cookie = Cookie::named(realm.cookie_name.clone());
+    cookie.set_path("/");
+    cookie
 }
 
-async fn signin(uri: Uri, headers: HeaderMap) -> AxumResult<impl IntoResponse> {
+// Returns whether the cookie holds a session that has expired or whose user was removed.
+fn record_stale_session(
+    config: &ServiceConfig,
+    realm: &Realm,
+    client: Option<IpAddr>,
+    headers: &HeaderMap,
+    jar: &SignedCookieJar,
+) -> bool {
+    let (kind, session) = match check_session(realm, jar) {
+        SessionCheck::Expired(session) => (AuditEventKind::SessionExpired, session),
+        SessionCheck::Revoked(session) => (AuditEventKind::SessionRevoked, session),
+        SessionCheck::Missing | SessionCheck::Valid(_) => return false,
+    };
+    config.audit.record(&AuditEvent {
+        user: Some(&session.subject),
+        client_ip: client,
+        user_agent: user_agent(headers),
+        ..AuditEvent::new(kind, &realm.name)
+    });
+    true
+}
+
+fn end_stale_session(
+    config: &ServiceConfig,
+    realm: &Realm,
+    client: Option<IpAddr>,
+    headers: &HeaderMap,
+    jar: SignedCookieJar,
+) -> SignedCookieJar {
+    match record_stale_session(config, realm, client, headers, &jar) {
+        true => jar.remove(removal_cookie(realm)),
+        false => jar,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct SignInQuery {
+    #[serde(rename = "rd")]
+    redirect_to: Option<String>,
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn signin(
+    CurrentConfig(config): CurrentConfig,
+    CurrentRealm(realm): CurrentRealm,
+    OriginalUri(uri): OriginalUri,
+    ClientAddress(client): ClientAddress,
+    connection: Option<ConnectInfo<ConnectionInfo>>,
+    headers: HeaderMap,
+    Query(query): Query<SignInQuery>,
+    SessionJar(jar): SessionJar,
+) -> AxumResult<impl IntoResponse> {
+    let jar = end_stale_session(&config, &realm, client, &headers, jar);
     if let Some(redirect_header) = headers.get(X_AUTH_REQUEST_REDIRECT) {
         let rd = redirect_header.to_str().ok().ok_or(StatusCode::BAD_REQUEST)?;
-        let rd = normalize_path(uri.path(), rd).ok_or(StatusCode::BAD_REQUEST)?;
+        let rd = normalize_path(uri.path(), rd, &realm.allowed_redirect_domains)
+            .ok_or(StatusCode::BAD_REQUEST)?;
         let signin_redirect =
             add_query_to_path(uri.path(), "rd", &rd).ok_or(StatusCode::BAD_REQUEST)?;
-        return Ok(Redirect::to(&signin_redirect).into_response());
+        return Ok((jar, Redirect::to(&signin_redirect)).into_response());
+    }
+    if let Some(sso_url) = &config.sso_url {
+        let host = request_host(&headers, trusts_forwarded(&config, &connection))
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        if !sso_url.host_str().is_some_and(|h| h.eq_ignore_ascii_case(host.host())) {
+            let rd = match query.redirect_to {
+                Some(r) if !r.is_empty() => r,
+                _ => "./userinfo".into(),
+            };
+            let rd = normalize_path(uri.path(), &rd, &realm.allowed_redirect_domains)
+                .ok_or(StatusCode::BAD_REQUEST)?;
+            let callback = normalize_path(uri.path(), "./handoff/callback", &[])
+                .and_then(|path| add_query_to_path(&path, "rd", &rd))
+                .ok_or(StatusCode::BAD_REQUEST)?;
+            let callback = format!("{}://{}{}", sso_url.scheme(), host, callback);
+            let mut handoff = sso_url.join("./handoff").map_err(|_| StatusCode::BAD_REQUEST)?;
+            handoff.query_pairs_mut().append_pair("callback", &callback);
+            return Ok((jar, Redirect::to(handoff.as_str())).into_response());
+        }
     }
-    Ok(get_signin_html().into_response())
+    Ok((jar, get_signin_html(realm.title.as_deref())).into_response())
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -106,18 +507,30 @@ struct SignOutQuery {
 }
 
 async fn signout(
-    uri: Uri,
+    CurrentConfig(config): CurrentConfig,
+    CurrentRealm(realm): CurrentRealm,
+    OriginalUri(uri): OriginalUri,
+    ClientAddress(client): ClientAddress,
+    headers: HeaderMap,
     Query(query): Query<SignOutQuery>,
-    jar: SignedCookieJar,
+    SessionJar(jar): SessionJar,
 ) -> AxumResult<impl IntoResponse> {
     let rd = match query.redirect_to {
         Some(r) if !r.is_empty() => r,
         _ => "./signin".into(),
     };
-    let rd = normalize_path(uri.path(), &rd).ok_or(StatusCode::BAD_REQUEST)?;
-    let mut cookie = Cookie::named(SESSION_COOKIE_NAME);
-    cookie.set_path("/");
-    let jar = jar.remove(cookie);
+    let rd = normalize_path(uri.path(), &rd, &realm.allowed_redirect_domains)
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    if let Some(cookie) = jar.get(&realm.cookie_name) {
+        let session = Session::from_cookie(cookie);
+        config.audit.record(&AuditEvent {
+            user: Some(&session.subject),
+            client_ip: client,
+            user_agent: user_agent(&headers),
+            ..AuditEvent::new(AuditEventKind::Signout, &realm.name)
+        });
+    }
+    let jar = jar.remove(removal_cookie(&realm));
     Ok((jar, Redirect::to(&rd)))
 }
 
@@ -128,52 +541,630 @@ struct AuthenticateRequest {
     redirect_to: Option<String>,
 }
 
-async fn authenticate(
-    State(config): State<ServiceConfig>,
-    uri: Uri,
-    jar: SignedCookieJar,
-    TypedHeader(origin): TypedHeader<Origin>,
-    TypedHeader(host): TypedHeader<Host>,
-    Json(req): Json<AuthenticateRequest>,
-) -> AxumResult<impl IntoResponse> {
-    if !check_origin(&origin, &host) {
-        log::debug!("invalid origin: origin = '{}', host = '{}'", origin, host);
-        return Err(JsonError::InvalidOrigin.into());
+fn record_failure(
+    config: &ServiceConfig,
+    realm: &Realm,
+    client: Option<IpAddr>,
+    headers: &HeaderMap,
+    username: &str,
+) {
+    let locked = if realm.users.contains_key(username) {
+        config.lockout.record_failure(&realm.name, username, None)
+    } else {
+        config.lockout.record_unknown_failure(&realm.name, username, None)
+    };
+    if let Some(until) = locked {
+        log::warn!("user '{}' locked out until {}", username, until);
+        config.audit.record(&AuditEvent {
+            user: Some(username),
+            client_ip: client,
+            user_agent: user_agent(headers),
+            reason: Some("too_many_failures"),
+            ..AuditEvent::new(AuditEventKind::AccountLocked, &realm.name)
+        });
+    }
+}
+
+async fn verify_request(
+    config: &ServiceConfig,
+    realm: &Arc<Realm>,
+    uri: &axum::http::Uri,
+    trust_forwarded: bool,
+    client: Option<IpAddr>,
+    headers: &HeaderMap,
+    req: &AuthenticateRequest,
+) -> Result<String, JsonError> {
+    if let Err(err) = check_origin(headers, trust_forwarded, &config.allowed_origins) {
+        log::info!("rejected authentication request: {}", err);
+        return Err(JsonError::InvalidOrigin);
     }
 
-    let rd = match req.redirect_to {
+    let rd = match &req.redirect_to {
         Some(r) if !r.is_empty() => r,
-        _ => "./userinfo".into(),
+        _ => "./userinfo",
     };
-    let rd = normalize_path(uri.path(), &rd).ok_or(JsonError::InvalidRedirect)?;
+    let rd = normalize_path(uri.path(), rd, &realm.allowed_redirect_domains)
+        .ok_or(JsonError::InvalidRedirect)?;
 
-    let ok = verify_password(config.users, &req.username, &req.password).map_err(|err| {
-        log::error!("password verification error: {}", err);
-        JsonError::InternalError
-    })?;
+    if let Err(retry_after) = config.rate_limits.check(client, &realm.name, &req.username, None) {
+        log::info!("rate limited authentication request for '{}'", req.username);
+        return Err(JsonError::RateLimited(retry_after));
+    }
+    if config.lockout.locked_until(&realm.name, &req.username, None).is_some() {
+        return Err(JsonError::AccountLocked);
+    }
+
+    let span = tracing::info_span!(
+        "verify_password",
+        realm = realm.name.as_str(),
+        otel.status_code = tracing::field::Empty,
+    );
+    let histogram = config.metrics.password_verification_duration.clone();
+    let (verifier, users) = (config.verifier.clone(), realm.clone());
+    let (username, password) = (req.username.clone(), req.password.clone());
+    let result = config
+        .hashing
+        .run(move || {
+            let _timer = histogram.start_timer();
+            verifier.verify(&users.users, &username, &password)
+        })
+        .instrument(span.clone())
+        .await;
+    let ok = match result {
+        Ok(Ok(ok)) => ok,
+        Err(err @ (HashingError::QueueFull | HashingError::QueueTimeout)) => {
+            log::warn!("rejected authentication request: {}", err);
+            return Err(JsonError::ServerBusy);
+        }
+        Ok(Err(err)) => {
+            log::error!("password verification error: {}", err);
+            span.record("otel.status_code", "ERROR");
+            return Err(JsonError::InternalError);
+        }
+        Err(err) => {
+            log::error!("password verification error: {}", err);
+            span.record("otel.status_code", "ERROR");
+            return Err(JsonError::InternalError);
+        }
+    };
+    drop(span);
     if !ok {
-        return Err(JsonError::InvalidCredential.into());
+        record_failure(config, realm, client, headers, &req.username);
+        return Err(JsonError::InvalidCredential);
     }
+    config.lockout.record_success(&realm.name, &req.username);
+    Ok(rd)
+}
 
-    log::info!("user '{}' authenticated", req.username);
+// Upgrades an outdated hash in the background once the password is known to be correct.
+fn rehash_password(
+    state: &ServiceState,
+    config: &ServiceConfig,
+    realm: &Realm,
+    req: &AuthenticateRequest,
+) {
+    let Some(old) = realm.users.get(&req.username) else {
+        return;
+    };
+    if !config.verifier.needs_rehash(old) {
+        return;
+    }
+    let Some(store) = config.user_store.clone() else {
+        log::warn!(
+            "password hash of user '{}' in realm '{}' is outdated, rehashing is recommended",
+            req.username,
+            realm.name
+        );
+        return;
+    };
+    let (state, verifier, hashing) =
+        (state.clone(), config.verifier.clone(), config.hashing.clone());
+    let (username, password, old) = (req.username.clone(), req.password.clone(), old.clone());
+    tokio::spawn(with_request_id(async move {
+        let new = match hashing.run(move || verifier.hash(&password)).await {
+            Ok(Ok(new)) => new,
+            Ok(Err(err)) => return log::error!("could not upgrade password hash: {}", err),
+            Err(err) => return log::warn!("could not upgrade password hash: {}", err),
+        };
+        // The store is rewritten with blocking file operations.
+        let upgrade = move || match store.replace_password(&username, &old, &new) {
+            Ok(true) => {
+                log::info!("upgraded password hash of user '{}'", username);
+                let updated = state.update(|config| ServiceConfig {
+                    realms: config.realms.replace_password(&username, &old, &new),
+                    ..config.clone()
+                });
+                if let Err(err) = updated {
+                    log::error!("could not update configuration: {}", err);
+                }
+            }
+            Ok(false) => {}
+            Err(err) => log::error!("could not upgrade password hash: {}", err),
+        };
+        if let Err(err) = tokio::task::spawn_blocking(with_request_id_blocking(upgrade)).await {
+            log::error!("could not upgrade password hash: {}", err);
+        }
+    }));
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn authenticate(
+    State(state): State<ServiceState>,
+    CurrentConfig(config): CurrentConfig,
+    CurrentRealm(realm): CurrentRealm,
+    OriginalUri(uri): OriginalUri,
+    SessionJar(jar): SessionJar,
+    ClientAddress(client): ClientAddress,
+    connection: Option<ConnectInfo<ConnectionInfo>>,
+    headers: HeaderMap,
+    Json(req): Json<AuthenticateRequest>,
+) -> AxumResult<impl IntoResponse> {
+    let trust_forwarded = trusts_forwarded(&config, &connection);
+    let result =
+        verify_request(&config, &realm, &uri, trust_forwarded, client, &headers, &req).await;
+    let event = AuditEvent {
+        user: Some(&req.username),
+        client_ip: client,
+        user_agent: user_agent(&headers),
+        ..AuditEvent::new(AuditEventKind::LoginSuccess, &realm.name)
+    };
+    let rd = match result {
+        Ok(rd) => {
+            config.audit.record(&event);
+            rehash_password(&state, &config, &realm, &req);
+            rd
+        }
+        Err(err) => {
+            let kind = AuditEventKind::LoginFailure;
+            config.audit.record(&AuditEvent { event: kind, reason: Some(err.kind()), ..event });
+            return Err(err.into());
+        }
+    };
 
     let session = Session { subject: req.username, issued_at: Utc::now() };
-    let jar = jar.add(session.to_cookie(SESSION_COOKIE_NAME));
-    Ok((jar, Json::from(json!({"redirect_to": rd, "username": session.subject}))))
+    let jar = jar.add(session_cookie(&realm, &session, config.sso_url.is_some()));
+    let user = Extension(RequestUser(session.subject.clone()));
+    Ok((jar, user, Json::from(json!({"redirect_to": rd, "username": session.subject}))))
+}
+
+fn certificate_session(realm: &Realm, info: &ConnectionInfo) -> Option<Session> {
+    if !realm.client_certificate_auth {
+        return None;
+    }
+    let username =
+        info.client_certificate_names.iter().find(|name| realm.users.contains_key(*name))?;
+    Some(Session { subject: username.clone(), issued_at: Utc::now() })
 }
 
 async fn userinfo(
-    State(config): State<ServiceConfig>,
-    jar: SignedCookieJar,
+    CurrentConfig(config): CurrentConfig,
+    CurrentRealm(realm): CurrentRealm,
+    ClientAddress(client): ClientAddress,
+    connection: Option<ConnectInfo<ConnectionInfo>>,
+    headers: HeaderMap,
+    SessionJar(jar): SessionJar,
 ) -> AxumResult<impl IntoResponse> {
-    let cookie = jar.get(SESSION_COOKIE_NAME).ok_or(JsonError::Unauthenticated)?;
-    let session = Session::from_cookie(cookie);
-    let options =
-        ValidationOptions { now: None, absolute_timeout: config.session_absolute_timeout };
-    if !session.is_valid(options) {
-        return Err(JsonError::Unauthenticated.into());
+    if let Some(cookie) = jar.get(&realm.cookie_name) {
+        let age = Utc::now() - Session::from_cookie(cookie).issued_at;
+        config.metrics.session_age.observe(age.num_milliseconds() as f64 / 1000.0);
     }
+    let session = valid_session(&realm, &jar)
+        .or_else(|| connection.and_then(|ConnectInfo(info)| certificate_session(&realm, &info)));
+    let result = if session.is_some() { "allow" } else { "deny" };
+    config.metrics.userinfo_requests.with_label_values(&[result]).inc();
+    if session.is_none() {
+        record_stale_session(&config, &realm, client, &headers, &jar);
+    }
+    let session = session.ok_or(JsonError::Unauthenticated)?;
     let headers = [(X_AUTH_REQUEST_USER, session.subject.clone())];
+    let user = Extension(RequestUser(session.subject.clone()));
     let resp = Json::from(session);
-    Ok((headers, resp))
+    Ok((headers, user, resp))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct HandoffQuery {
+    callback: String,
+}
+
+async fn handoff(
+    CurrentConfig(config): CurrentConfig,
+    CurrentRealm(realm): CurrentRealm,
+    OriginalUri(uri): OriginalUri,
+    Query(query): Query<HandoffQuery>,
+    SessionJar(jar): SessionJar,
+) -> AxumResult<impl IntoResponse> {
+    let mut callback = Url::parse(&query.callback)
+        .ok()
+        .filter(|url| realm.allowed_redirect_domains.iter().any(|d| d.matches(url)))
+        .ok_or(JsonError::InvalidRedirect)?;
+    let domain = callback.host_str().ok_or(JsonError::InvalidRedirect)?.to_owned();
+
+    let Some(session) = valid_session(&realm, &jar) else {
+        let current = uri.path_and_query().map_or(uri.path(), |pq| pq.as_str());
+        let signin = normalize_path(uri.path(), "./signin", &[])
+            .and_then(|path| add_query_to_path(&path, "rd", current))
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        return Ok(Redirect::to(&signin));
+    };
+
+    let grant = HandoffGrant {
+        password_hash: realm.users.get(&session.subject).cloned().unwrap_or_default(),
+        subject: session.subject,
+        realm: realm.name.clone(),
+    };
+    let code = config.handoff_codes.issue(grant, &domain, None);
+    callback.query_pairs_mut().append_pair("code", &code);
+    Ok(Redirect::to(callback.as_str()))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct HandoffCallbackQuery {
+    code: String,
+    #[serde(rename = "rd")]
+    redirect_to: Option<String>,
+}
+
+async fn handoff_callback(
+    CurrentConfig(config): CurrentConfig,
+    CurrentRealm(realm): CurrentRealm,
+    OriginalUri(uri): OriginalUri,
+    connection: Option<ConnectInfo<ConnectionInfo>>,
+    headers: HeaderMap,
+    Query(query): Query<HandoffCallbackQuery>,
+    SessionJar(jar): SessionJar,
+) -> AxumResult<impl IntoResponse> {
+    let host = request_host(&headers, trusts_forwarded(&config, &connection))
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let rd = match query.redirect_to {
+        Some(r) if !r.is_empty() => r,
+        _ => "../userinfo".into(),
+    };
+    let rd = normalize_path(uri.path(), &rd, &realm.allowed_redirect_domains)
+        .ok_or(JsonError::InvalidRedirect)?;
+
+    // A code issued by another realm is only accepted if both realms were configured from the
+    // same user entry, rather than from unrelated users that happen to share a name.
+    let grant = config.handoff_codes.redeem(&query.code, host.host(), None).filter(|grant| {
+        let password_hash = realm.users.get(&grant.subject);
+        password_hash.is_some_and(|h| grant.realm == realm.name || *h == grant.password_hash)
+    });
+    let Some(HandoffGrant { subject, .. }) = grant else {
+        log::info!("rejected invalid or expired handoff code for '{}'", host.host());
+        return Err(JsonError::Unauthenticated.into());
+    };
+    log::info!("user '{}' signed in to '{}' by handoff", subject, host.host());
+
+    let session = Session { subject, issued_at: Utc::now() };
+    let jar = jar.add(session_cookie(&realm, &session, config.sso_url.is_some()));
+    Ok((jar, Redirect::to(&rd)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::service::audit::AuditSink;
+    use crate::service::lockout::LockoutPolicy;
+
+    fn config(key: Vec<u8>, users: &[&str]) -> ServiceConfig {
+        let users = users.iter().map(|u| (u.to_string(), String::new())).collect();
+        ServiceConfig { session_secret_key: key, ..ServiceConfig::for_tests(users) }
+    }
+
+    fn sso_config(key: Vec<u8>) -> ServiceConfig {
+        let mut config = config(key, &["alice"]);
+        config.sso_url = Some("https://sso.example.com/auth/".parse().unwrap());
+        let mut realm = Realm::clone(&config.realms.select(None, "/"));
+        realm.allowed_redirect_domains = vec!["app.example.com".parse().unwrap()];
+        config.realms = RealmTable::new(vec![], realm);
+        config
+    }
+
+    // Alice is configured from the same entry in both realms, while the bobs are unrelated.
+    fn cross_realm_config(key: Vec<u8>) -> ServiceConfig {
+        let mut config = sso_config(key);
+        let mut default = Realm::clone(&config.realms.select(None, "/"));
+        default.users = [("alice", "a"), ("bob", "b1")].map(|(u, h)| (u.into(), h.into())).into();
+        default.allowed_redirect_domains.push("media.example.com".parse().unwrap());
+        let media = Realm {
+            name: "media".into(),
+            hosts: vec!["media.example.com".into()],
+            cookie_name: "session_media".into(),
+            users: [("alice", "a"), ("bob", "b2")].map(|(u, h)| (u.into(), h.into())).into(),
+            ..default.clone()
+        };
+        config.realms = RealmTable::new(vec![media], default);
+        config
+    }
+
+    async fn handoff(service: &Router, key: &[u8], subject: &str, callback: &str) -> Response {
+        let cookie = signed_session(key, "session", subject);
+        let uri = format!("/handoff?callback={}", callback);
+        let resp = get(service, &uri, "sso.example.com", Some(&cookie)).await;
+        assert_eq!(StatusCode::SEE_OTHER, resp.status());
+        let callback = location(&resp);
+        get(service, &path_and_query(&callback), callback.host_str().unwrap(), None).await
+    }
+
+    async fn get(service: &Router, uri: &str, host: &str, cookie: Option<&str>) -> Response {
+        let mut req = Request::get(uri).header(header::HOST, host);
+        if let Some(cookie) = cookie {
+            req = req.header(header::COOKIE, cookie);
+        }
+        service.clone().oneshot(req.body(Body::empty()).unwrap()).await.unwrap()
+    }
+
+    fn location(resp: &Response) -> Url {
+        let location = resp.headers()[header::LOCATION].to_str().unwrap();
+        Url::parse("https://base.invalid/").unwrap().join(location).unwrap()
+    }
+
+    fn path_and_query(url: &Url) -> String {
+        format!("{}?{}", url.path(), url.query().unwrap_or_default())
+    }
+
+    fn set_cookie(resp: &Response) -> String {
+        let value = resp.headers()[header::SET_COOKIE].to_str().unwrap();
+        value.split(';').next().unwrap().to_owned()
+    }
+
+    fn signed_session(key: &[u8], cookie_name: &str, subject: &str) -> String {
+        let session = Session { subject: subject.into(), issued_at: Utc::now() };
+        let cookie = session.to_cookie(cookie_name).into_owned();
+        let jar = SignedCookieJar::new(Key::from(key)).add(cookie);
+        set_cookie(&jar.into_response())
+    }
+
+    fn users(config: &ServiceConfig) -> Vec<String> {
+        config.realms.iter().flat_map(|realm| realm.users.keys().cloned()).collect()
+    }
+
+    #[test]
+    fn test_state_replace() {
+        let key = ServiceConfig::generate_key();
+        let state = ServiceState::new(config(key.clone(), &["alice"])).unwrap();
+        let before = state.config();
+        state.replace(config(key.clone(), &["bob"])).unwrap();
+
+        assert_eq!(vec!["alice".to_string()], users(&before));
+        assert_eq!(vec!["bob".to_string()], users(&state.config()));
+        assert_eq!(Key::from(&key).master(), state.0.load().key.master());
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_loaded_once() {
+        let key = ServiceConfig::generate_key();
+        let state = ServiceState::new(config(key.clone(), &["alice"])).unwrap();
+        let (mut parts, _) = Request::new(()).into_parts();
+        parts.extensions.insert(state.0.load_full());
+        state.replace(config(key, &["bob"])).unwrap();
+
+        let CurrentConfig(config) =
+            CurrentConfig::from_request_parts(&mut parts, &state).await.unwrap();
+        assert_eq!(vec!["alice".to_string()], users(&config));
+    }
+
+    #[tokio::test]
+    async fn test_userinfo_client_certificate() {
+        let service = config(ServiceConfig::generate_key(), &["alice"]).build().unwrap();
+        let request = |name: &str| {
+            let info = ConnectionInfo {
+                peer_address: None,
+                client_certificate_names: vec![name.to_owned()],
+            };
+            Request::get("/userinfo").extension(ConnectInfo(info)).body(Body::empty()).unwrap()
+        };
+
+        let resp = service.clone().oneshot(request("alice")).await.unwrap();
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!("alice", resp.headers()[X_AUTH_REQUEST_USER]);
+        let resp = service.oneshot(request("bob")).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
+        let mut config = config(ServiceConfig::generate_key(), &["alice"]);
+        let mut realm = Realm::clone(&config.realms.select(None, "/"));
+        realm.client_certificate_auth = false;
+        config.realms = RealmTable::new(vec![], realm);
+        let resp = config.build().unwrap().oneshot(request("alice")).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_without_users() {
+        let service = config(ServiceConfig::generate_key(), &[]).build().unwrap();
+        let req = Request::post("/authenticate")
+            .header(header::HOST, "auth.example.com")
+            .header(header::ORIGIN, "https://auth.example.com")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"username":"alice","password":"p@ssw0rd"}"#))
+            .unwrap();
+        let resp = service.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+        let error = resp.extensions().get::<RequestError>().map(|RequestError(kind)| *kind);
+        assert_eq!(Some("invalid_credential"), error);
+    }
+
+    #[tokio::test]
+    async fn test_lockout_unknown_user() {
+        let policy = LockoutPolicy { max_failures: 3, duration: Duration::from_secs(600) };
+        let hash = "pbkdf2_sha256$1000$seasalt$Ct1LhKwHRy70kFSNQPNOcrZkExl+bUTgJPa7OLal4Dw=";
+        let users = HashMap::from([("alice".to_string(), hash.to_string())]);
+        let config = ServiceConfig {
+            lockout: LockoutStore::new(policy, None),
+            ..ServiceConfig::for_tests(users)
+        };
+        let service = config.build().unwrap();
+        let mut responses = HashMap::new();
+        for username in ["alice", "mallory"] {
+            let mut seen = vec![];
+            for _ in 0..4 {
+                let body = json!({"username": username, "password": "wrong"}).to_string();
+                let req = Request::post("/authenticate")
+                    .header(header::HOST, "auth.example.com")
+                    .header(header::ORIGIN, "https://auth.example.com")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body))
+                    .unwrap();
+                let resp = service.clone().oneshot(req).await.unwrap();
+                let error = resp.extensions().get::<RequestError>().map(|RequestError(kind)| *kind);
+                seen.push((resp.status(), error));
+            }
+            responses.insert(username, seen);
+        }
+        let expected = vec![
+            (StatusCode::BAD_REQUEST, Some("invalid_credential")),
+            (StatusCode::BAD_REQUEST, Some("invalid_credential")),
+            (StatusCode::BAD_REQUEST, Some("invalid_credential")),
+            (StatusCode::FORBIDDEN, Some("account_locked")),
+        ];
+        assert_eq!(expected, responses["alice"]);
+        assert_eq!(expected, responses["mallory"]);
+    }
+
+    #[tokio::test]
+    async fn test_handoff_round_trip() {
+        let key = ServiceConfig::generate_key();
+        let service = sso_config(key.clone()).build().unwrap();
+
+        let resp = get(&service, "/signin", "APP.example.com", None).await;
+        assert_eq!(StatusCode::SEE_OTHER, resp.status());
+        let handoff = location(&resp);
+        assert_eq!(Some("sso.example.com"), handoff.host_str());
+        assert_eq!("/auth/handoff", handoff.path());
+
+        // A proxy in front of the sso host strips the /auth prefix.
+        let uri = path_and_query(&handoff).replacen("/auth", "", 1);
+        let cookie = signed_session(&key, "session", "alice");
+        let resp = get(&service, &uri, "sso.example.com", Some(&cookie)).await;
+        assert_eq!(StatusCode::SEE_OTHER, resp.status());
+        let callback = location(&resp);
+        assert_eq!(Some("app.example.com"), callback.host_str());
+        assert_eq!("/handoff/callback", callback.path());
+
+        let uri = path_and_query(&callback);
+        let resp = get(&service, &uri, "app.example.com", None).await;
+        assert_eq!(StatusCode::SEE_OTHER, resp.status());
+        assert_eq!("/userinfo", location(&resp).path());
+
+        let cookie = set_cookie(&resp);
+        let resp = get(&service, "/userinfo", "app.example.com", Some(&cookie)).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!("alice", resp.headers()[X_AUTH_REQUEST_USER]);
+        let resp = get(&service, &uri, "app.example.com", None).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+    }
+
+    #[tokio::test]
+    async fn test_handoff_cross_realm() {
+        let key = ServiceConfig::generate_key();
+        let service = cross_realm_config(key.clone()).build().unwrap();
+        let callback = "https://media.example.com/handoff/callback";
+
+        let resp = handoff(&service, &key, "alice", callback).await;
+        assert_eq!(StatusCode::SEE_OTHER, resp.status());
+        assert!(set_cookie(&resp).starts_with("session_media="));
+        let resp = handoff(&service, &key, "bob", callback).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+    }
+
+    #[tokio::test]
+    async fn test_handoff_same_realm() {
+        let key = ServiceConfig::generate_key();
+        let service = cross_realm_config(key.clone()).build().unwrap();
+        let callback = "https://app.example.com/handoff/callback";
+
+        let resp = handoff(&service, &key, "bob", callback).await;
+        assert_eq!(StatusCode::SEE_OTHER, resp.status());
+        assert!(set_cookie(&resp).starts_with("session="));
+    }
+
+    #[derive(Debug, Default)]
+    struct MemorySink(std::sync::Mutex<Vec<String>>);
+
+    impl AuditSink for Arc<MemorySink> {
+        fn name(&self) -> &'static str {
+            "memory"
+        }
+
+        fn send(&self, _event: &AuditEvent, line: &str) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(line.into());
+            Ok(())
+        }
+    }
+
+    impl MemorySink {
+        fn events(&self) -> Vec<(String, String)> {
+            let lines = self.0.lock().unwrap();
+            let events = lines.iter().map(|line| {
+                let event: serde_json::Value = serde_json::from_str(line).unwrap();
+                (event["event"].as_str().unwrap().into(), event["user"].as_str().unwrap().into())
+            });
+            events.collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stale_session_recorded_on_signin() {
+        let key = ServiceConfig::generate_key();
+        let sink = Arc::new(MemorySink::default());
+        let mut config = config(key.clone(), &["alice"]);
+        config.audit = Arc::new(AuditLog::new(vec![Box::new(sink.clone())]));
+        let service = config.build().unwrap();
+        let cookie = signed_session(&key, "session", "bob");
+
+        let resp = get(&service, "/signin", "app.example.com", Some(&cookie)).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!("session=", set_cookie(&resp));
+        let expected = vec![("session_revoked".to_string(), "bob".to_string())];
+        assert_eq!(expected, sink.events());
+    }
+
+    #[tokio::test]
+    async fn test_stale_session_recorded_on_userinfo() {
+        let key = ServiceConfig::generate_key();
+        let sink = Arc::new(MemorySink::default());
+        let mut config = config(key.clone(), &["alice"]);
+        config.audit = Arc::new(AuditLog::new(vec![Box::new(sink.clone())]));
+        let service = config.build().unwrap();
+
+        let revoked = signed_session(&key, "session", "bob");
+        let session =
+            Session { subject: "alice".into(), issued_at: Utc::now() - chrono::Duration::hours(2) };
+        let jar = SignedCookieJar::new(Key::from(&key)).add(session.to_cookie("session"));
+        let expired = set_cookie(&jar.into_response());
+        let valid = signed_session(&key, "session", "alice");
+        for cookie in [Some(&revoked), Some(&expired), Some(&valid), None] {
+            get(&service, "/userinfo", "app.example.com", cookie.map(String::as_str)).await;
+        }
+        let expected = vec![
+            ("session_revoked".to_string(), "bob".to_string()),
+            ("session_expired".to_string(), "alice".to_string()),
+        ];
+        assert_eq!(expected, sink.events());
+    }
+
+    #[tokio::test]
+    async fn test_signin_on_sso_host() {
+        let service = sso_config(ServiceConfig::generate_key()).build().unwrap();
+        let resp = get(&service, "/signin", "SSO.Example.com", None).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+
+    #[test]
+    fn test_state_invalid_key() {
+        let short = || config(b"short".to_vec(), &["alice"]);
+        assert!(matches!(ServiceState::new(short()), Err(ServiceError::InvalidKey(_))));
+
+        let state = ServiceState::new(config(ServiceConfig::generate_key(), &["alice"])).unwrap();
+        assert!(state.replace(short()).is_err());
+        let updated = state.update(|config| ServiceConfig {
+            session_secret_key: b"short".to_vec(),
+            ..config.clone()
+        });
+        assert!(updated.is_err());
+        assert_eq!(vec!["alice".to_string()], users(&state.config()));
+    }
 }
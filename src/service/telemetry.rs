@@ -0,0 +1,243 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use axum::extract::MatchedPath;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use hyper::client::HttpConnector;
+use hyper::Client;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_http::hyper::HyperClient;
+use opentelemetry_http::HeaderExtractor;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{self as sdktrace, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use rustls::{ClientConfig, RootCertStore};
+use tracing::field::Empty;
+use tracing::{Instrument, Level, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{Layer, Registry};
+use url::Url;
+
+use super::access_log::RequestUser;
+
+const EXPORT_PATH: &str = "/v1/traces";
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub async fn trace_request<B>(req: Request<B>, next: Next<B>) -> Response {
+    let route = match req.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_owned(),
+        None => req.uri().path().to_owned(),
+    };
+    let method = req.method().to_string();
+    let span = tracing::info_span!(
+        "request",
+        otel.name = format!("{} {}", method, route),
+        otel.kind = "server",
+        otel.status_code = Empty,
+        http.request.method = method,
+        http.route = route,
+        url.path = req.uri().path(),
+        http.response.status_code = Empty,
+        enduser.id = Empty,
+    );
+    span.set_parent(TraceContextPropagator::new().extract(&HeaderExtractor(req.headers())));
+
+    let resp = next.run(req).instrument(span.clone()).await;
+    let status = resp.status();
+    span.record("http.response.status_code", status.as_u16() as i64);
+    if let Some(RequestUser(user)) = resp.extensions().get() {
+        span.record("enduser.id", user.as_str());
+    }
+    if status.is_server_error() {
+        span.record("otel.status_code", "ERROR");
+    }
+    resp
+}
+
+// The exporter appends the path of the traces endpoint itself.
+pub fn parse_endpoint(endpoint: &Url) -> Result<Url> {
+    if !matches!(endpoint.scheme(), "http" | "https") {
+        bail!("otlp endpoint must be an http or https url");
+    }
+    if endpoint.host_str().is_none() {
+        bail!("otlp endpoint has no host");
+    }
+    let mut endpoint = endpoint.clone();
+    if let Some(path) = endpoint.path().strip_suffix(EXPORT_PATH).map(str::to_owned) {
+        endpoint.set_path(&path);
+    }
+    Ok(endpoint)
+}
+
+// Collectors are verified against the system's certificate authorities. Without any, only
+// plain http endpoints can be reached.
+fn http_client() -> Client<HttpsConnector<HttpConnector>> {
+    let mut roots = RootCertStore::empty();
+    match rustls_native_certs::load_native_certs() {
+        Ok(certs) => {
+            for cert in certs {
+                let _ = roots.add(&rustls::Certificate(cert.0));
+            }
+        }
+        Err(err) => log::warn!("could not load system certificates: {}", err),
+    }
+    let tls = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let connector =
+        HttpsConnectorBuilder::new().with_tls_config(tls).https_or_http().enable_http1().build();
+    Client::builder().build(connector)
+}
+
+fn tracer_provider(endpoint: &Url, service_name: String) -> Result<TracerProvider> {
+    let exporter = opentelemetry_otlp::new_exporter()
+        .http()
+        .with_endpoint(endpoint.as_str().trim_end_matches('/'))
+        .with_http_client(HyperClient::new_with_timeout(http_client(), EXPORT_TIMEOUT))
+        .build_span_exporter()
+        .context("could not create otlp exporter")?;
+    let resource = Resource::new([KeyValue::new("service.name", service_name)]);
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_config(sdktrace::config().with_resource(resource))
+        .build())
+}
+
+// Only the spans of this crate are exported, not those of its dependencies.
+fn subscriber(provider: &TracerProvider) -> impl Subscriber + Send + Sync {
+    let tracer = provider.versioned_tracer(
+        env!("CARGO_PKG_NAME"),
+        Some(env!("CARGO_PKG_VERSION")),
+        None::<&str>,
+        None,
+    );
+    let filter = Targets::new().with_target(env!("CARGO_CRATE_NAME"), Level::INFO);
+    Registry::default().with(tracing_opentelemetry::layer().with_tracer(tracer).with_filter(filter))
+}
+
+#[derive(Debug)]
+pub struct ExporterHandle {
+    provider: TracerProvider,
+}
+
+impl ExporterHandle {
+    // Exports the spans that are still queued, such as those of the last requests served.
+    pub async fn shutdown(self) {
+        let provider = self.provider;
+        let results = tokio::task::spawn_blocking(move || provider.force_flush()).await;
+        for err in results.unwrap_or_default().into_iter().filter_map(Result::err) {
+            log::warn!("could not export spans: {}", err);
+        }
+    }
+}
+
+pub fn init(endpoint: &Url, service_name: String) -> Result<ExporterHandle> {
+    let provider = tracer_provider(endpoint, service_name)?;
+    opentelemetry::global::set_error_handler(|err| log::warn!("{}", err))
+        .context("could not set opentelemetry error handler")?;
+    tracing::subscriber::set_global_default(subscriber(&provider))
+        .context("could not set tracing subscriber")?;
+    Ok(ExporterHandle { provider })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+    use opentelemetry_proto::tonic::common::v1::any_value::Value;
+    use prost::Message;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const TRACEPARENT_VALUE: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_parse_endpoint() {
+        let endpoint = Url::parse("http://localhost:4318").unwrap();
+        let expected = "http://localhost:4318/";
+        assert_eq!(expected, parse_endpoint(&endpoint).unwrap().as_str());
+
+        let endpoint = Url::parse("https://collector.example.com/otlp/v1/traces").unwrap();
+        let expected = "https://collector.example.com/otlp";
+        assert_eq!(expected, parse_endpoint(&endpoint).unwrap().as_str());
+
+        let endpoint = Url::parse("grpc://localhost:4317").unwrap();
+        assert!(parse_endpoint(&endpoint).is_err());
+    }
+
+    async fn read_request(stream: &mut tokio::net::TcpStream) -> (String, Vec<u8>) {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") else {
+                continue;
+            };
+            let head = String::from_utf8(request[..end].to_vec()).unwrap().to_lowercase();
+            let length = head
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length: "))
+                .map_or(0, |len| len.parse().unwrap());
+            if request.len() >= end + 4 + length {
+                return (head, request[end + 4..].to_vec());
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_export_request_span() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let collector = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request = read_request(&mut stream).await;
+            stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n").await.unwrap();
+            request
+        });
+
+        let endpoint = Url::parse(&format!("http://{}/v1/traces", address)).unwrap();
+        let provider = tracer_provider(&parse_endpoint(&endpoint).unwrap(), "test".into());
+        let exporter = ExporterHandle { provider: provider.unwrap() };
+        let router = Router::new()
+            .route("/userinfo", get(|| async { StatusCode::OK }))
+            .route_layer(middleware::from_fn(trace_request));
+        let req = Request::get("/userinfo")
+            .header("traceparent", TRACEPARENT_VALUE)
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let guard = tracing::subscriber::set_default(subscriber(&exporter.provider));
+        let resp = router.oneshot(req).await.unwrap();
+        drop(guard);
+        assert_eq!(StatusCode::OK, resp.status());
+        exporter.shutdown().await;
+
+        let (head, body) = collector.await.unwrap();
+        assert!(head.starts_with("post /v1/traces http/1.1\r\n"), "{}", head);
+        let request = ExportTraceServiceRequest::decode(body.as_slice()).unwrap();
+        let span = &request.resource_spans[0].scope_spans[0].spans[0];
+        assert_eq!("4bf92f3577b34da6a3ce929d0e0e4736", hex::encode(&span.trace_id));
+        assert_eq!("00f067aa0ba902b7", hex::encode(&span.parent_span_id));
+        assert_eq!("GET /userinfo", span.name);
+        let status = span
+            .attributes
+            .iter()
+            .find(|a| a.key == "http.response.status_code")
+            .and_then(|a| a.value.as_ref()?.value.clone());
+        assert_eq!(Some(Value::IntValue(200)), status);
+    }
+}
@@ -0,0 +1,353 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use fd_lock::RwLock;
+use tempfile::NamedTempFile;
+use thiserror::Error;
+use toml_edit::{value, Document, InlineTable, Item, Table, TableLike, Value};
+
+#[derive(Debug, Error)]
+pub enum UserStoreError {
+    #[error("could not access user store: {0}")]
+    Io(#[from] io::Error),
+    #[error("could not parse user store: {0}")]
+    Parse(#[from] toml_edit::TomlError),
+    #[error("users in user store must be an array of tables")]
+    InvalidUsers,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserEntry {
+    pub username: String,
+    pub disabled: bool,
+}
+
+// Edits the users of a config or users file in place, keeping its comments and formatting.
+#[derive(Debug)]
+pub struct UserStore {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+fn user_tables(item: &mut Item) -> Vec<&mut dyn TableLike> {
+    match item {
+        Item::ArrayOfTables(tables) => tables.iter_mut().map(|t| t as &mut dyn TableLike).collect(),
+        Item::Value(value) => match value.as_array_mut() {
+            Some(array) => array
+                .iter_mut()
+                .filter_map(|v| v.as_inline_table_mut())
+                .map(|t| t as &mut dyn TableLike)
+                .collect(),
+            None => vec![],
+        },
+        _ => vec![],
+    }
+}
+
+fn all_user_tables(doc: &mut Document) -> Vec<&mut dyn TableLike> {
+    let mut tables = vec![];
+    for (key, item) in doc.as_table_mut().iter_mut() {
+        match key.get() {
+            "users" => tables.extend(user_tables(item)),
+            "realms" => {
+                let realms = item.as_array_of_tables_mut().into_iter().flat_map(|r| r.iter_mut());
+                for users in realms.filter_map(|realm| realm.get_mut("users")) {
+                    tables.extend(user_tables(users));
+                }
+            }
+            _ => {}
+        }
+    }
+    tables
+}
+
+fn top_level_user_tables(doc: &mut Document) -> Vec<&mut dyn TableLike> {
+    doc.get_mut("users").map(user_tables).unwrap_or_default()
+}
+
+fn find_user<'a>(doc: &'a mut Document, username: &str) -> Option<&'a mut dyn TableLike> {
+    let tables = top_level_user_tables(doc);
+    tables.into_iter().find(|t| get_str(*t, "username") == Some(username))
+}
+
+fn get_str<'a>(table: &'a dyn TableLike, key: &str) -> Option<&'a str> {
+    table.get(key).and_then(|item| item.as_str())
+}
+
+fn is_user(table: &dyn TableLike, username: &str) -> bool {
+    get_str(table, "username") == Some(username)
+}
+
+impl UserStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, lock: Mutex::new(()) }
+    }
+
+    // Other processes are excluded with a lock on a separate file, as the store itself is
+    // replaced on every write. A missing store is created with only the owner's permissions.
+    fn update<T>(
+        &self,
+        f: impl FnOnce(&mut Document) -> Result<(T, bool), UserStoreError>,
+    ) -> Result<T, UserStoreError> {
+        let _lock = self.lock.lock().unwrap();
+        let lock_file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.with_extension("lock"))?;
+        let mut lock_file = RwLock::new(lock_file);
+        let _lock_file = lock_file.write()?;
+        let (mut doc, permissions) = match fs::read_to_string(&self.path) {
+            Ok(content) => (content.parse()?, fs::metadata(&self.path)?.permissions()),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                (Document::new(), fs::Permissions::from_mode(0o600))
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let (result, changed) = f(&mut doc)?;
+        if changed {
+            let dir = self.path.parent().filter(|dir| !dir.as_os_str().is_empty());
+            let mut temp = NamedTempFile::new_in(dir.unwrap_or(Path::new(".")))?;
+            temp.write_all(doc.to_string().as_bytes())?;
+            temp.as_file().set_permissions(permissions)?;
+            temp.persist(&self.path).map_err(|err| err.error)?;
+        }
+        Ok(result)
+    }
+
+    // Readers share the lock and do not create the lock file, which only writers need.
+    fn read(&self) -> Result<Document, UserStoreError> {
+        let mut lock_file = match File::open(self.path.with_extension("lock")) {
+            Ok(file) => Some(RwLock::new(file)),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+        let _lock_file = lock_file.as_mut().map(|lock| lock.read()).transpose()?;
+        match fs::read_to_string(&self.path) {
+            Ok(content) => Ok(content.parse()?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Document::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn list_users(&self) -> Result<Vec<UserEntry>, UserStoreError> {
+        let mut doc = self.read()?;
+        let users = top_level_user_tables(&mut doc)
+            .into_iter()
+            .filter_map(|table| {
+                let username = get_str(table, "username")?.into();
+                let disabled = table.get("disabled").and_then(|d| d.as_bool()).unwrap_or(false);
+                Some(UserEntry { username, disabled })
+            })
+            .collect();
+        Ok(users)
+    }
+
+    // Returns false without changing the store if the user already exists.
+    pub fn add_user(&self, username: &str, password: &str) -> Result<bool, UserStoreError> {
+        self.update(|doc| {
+            if find_user(doc, username).is_some() {
+                return Ok((false, false));
+            }
+            let users = doc.entry("users").or_insert(Item::ArrayOfTables(Default::default()));
+            match users {
+                Item::ArrayOfTables(tables) => {
+                    let mut table = Table::new();
+                    table.insert("username", value(username));
+                    table.insert("password", value(password));
+                    tables.push(table);
+                }
+                Item::Value(Value::Array(array)) => {
+                    let mut table = InlineTable::new();
+                    table.insert("username", username.into());
+                    table.insert("password", password.into());
+                    array.push(table);
+                }
+                _ => return Err(UserStoreError::InvalidUsers),
+            }
+            Ok((true, true))
+        })
+    }
+
+    pub fn remove_user(&self, username: &str) -> Result<bool, UserStoreError> {
+        self.update(|doc| {
+            let removed = match doc.get_mut("users") {
+                // The first entry keeps its formatting even if it is the one removed.
+                Some(Item::ArrayOfTables(tables)) => {
+                    let (len, decor) = (tables.len(), tables.get(0).map(|t| t.decor().clone()));
+                    tables.retain(|table| !is_user(table, username));
+                    if let (Some(first), Some(decor)) = (tables.get_mut(0), decor) {
+                        *first.decor_mut() = decor;
+                    }
+                    tables.len() < len
+                }
+                Some(Item::Value(Value::Array(array))) => {
+                    let (len, decor) = (array.len(), array.get(0).map(|v| v.decor().clone()));
+                    array.retain(|v| v.as_inline_table().is_none_or(|t| !is_user(t, username)));
+                    if let (Some(first), Some(decor)) = (array.get_mut(0), decor) {
+                        *first.decor_mut() = decor;
+                    }
+                    array.len() < len
+                }
+                _ => false,
+            };
+            Ok((removed, removed))
+        })
+    }
+
+    pub fn set_password(&self, username: &str, password: &str) -> Result<bool, UserStoreError> {
+        self.update(|doc| {
+            let Some(table) = find_user(doc, username) else {
+                return Ok((false, false));
+            };
+            table.insert("password", value(password));
+            Ok((true, true))
+        })
+    }
+
+    pub fn set_disabled(&self, username: &str, disabled: bool) -> Result<bool, UserStoreError> {
+        self.update(|doc| {
+            let Some(table) = find_user(doc, username) else {
+                return Ok((false, false));
+            };
+            match disabled {
+                true => table.insert("disabled", value(true)),
+                false => table.remove("disabled"),
+            };
+            Ok((true, true))
+        })
+    }
+
+    // Replaces the password of every entry of the user that still has the old hash, and
+    // returns whether any entry was updated.
+    pub fn replace_password(
+        &self,
+        username: &str,
+        old: &str,
+        new: &str,
+    ) -> Result<bool, UserStoreError> {
+        self.update(|doc| {
+            let mut changed = false;
+            for table in all_user_tables(doc) {
+                if get_str(table, "username") == Some(username)
+                    && get_str(table, "password") == Some(old)
+                {
+                    table.insert("password", value(new));
+                    changed = true;
+                }
+            }
+            Ok((changed, changed))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"# users
+[[users]]
+username = "alice"
+password = "old"
+
+[[users]]
+username = "bob"
+password = "old"
+
+[[realms]]
+name = "work"
+users = ["alice", { username = "alice", password = "old" }]
+
+[[realms]]
+name = "media"
+
+[[realms.users]]
+username = "alice"
+password = "other"
+"#;
+
+    #[test]
+    fn test_replace_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, CONFIG).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        fs::write(dir.path().join("config.tmp"), "unrelated").unwrap();
+        let store = UserStore::new(path.clone());
+
+        assert!(store.replace_password("alice", "old", "new").unwrap());
+        assert_eq!(0o640, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+        assert_eq!("unrelated", fs::read_to_string(dir.path().join("config.tmp")).unwrap());
+        let expected = CONFIG.replacen(r#"password = "old""#, r#"password = "new""#, 1).replace(
+            r#"{ username = "alice", password = "old" }"#,
+            r#"{ username = "alice", password = "new" }"#,
+        );
+        assert_eq!(expected, fs::read_to_string(&path).unwrap());
+        assert!(!store.replace_password("alice", "old", "new").unwrap());
+    }
+
+    #[test]
+    fn test_list_users_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = UserStore::new(dir.path().join("users.toml"));
+        assert_eq!(Vec::<UserEntry>::new(), store.list_users().unwrap());
+        assert_eq!(0, fs::read_dir(dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn test_manage_users() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.toml");
+        let store = UserStore::new(path.clone());
+
+        assert!(store.add_user("alice", "hash1").unwrap());
+        assert!(store.add_user("bob", "hash2").unwrap());
+        assert!(!store.add_user("alice", "hash3").unwrap());
+        assert!(store.set_password("alice", "hash4").unwrap());
+        assert!(store.set_disabled("bob", true).unwrap());
+        assert!(!store.set_disabled("carol", true).unwrap());
+        let expected = r#"[[users]]
+username = "alice"
+password = "hash4"
+
+[[users]]
+username = "bob"
+password = "hash2"
+disabled = true
+"#;
+        assert_eq!(expected, fs::read_to_string(&path).unwrap());
+        assert_eq!(0o600, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+
+        let expected = vec![
+            UserEntry { username: "alice".into(), disabled: false },
+            UserEntry { username: "bob".into(), disabled: true },
+        ];
+        assert_eq!(expected, store.list_users().unwrap());
+        assert!(store.remove_user("alice").unwrap());
+        assert!(!store.remove_user("alice").unwrap());
+        assert!(store.set_disabled("bob", false).unwrap());
+        let expected = "[[users]]\nusername = \"bob\"\npassword = \"hash2\"\n";
+        assert_eq!(expected, fs::read_to_string(&path).unwrap());
+    }
+
+    #[test]
+    fn test_manage_inline_users() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.toml");
+        fs::write(
+            &path,
+            "# managed by staticauth\nusers = [{ username = \"alice\", password = \"a\" }]\n",
+        )
+        .unwrap();
+        let store = UserStore::new(path.clone());
+
+        assert!(store.add_user("bob", "b").unwrap());
+        assert!(store.remove_user("alice").unwrap());
+        let expected =
+            "# managed by staticauth\nusers = [{ username = \"bob\", password = \"b\" }]\n";
+        assert_eq!(expected, fs::read_to_string(&path).unwrap());
+    }
+}